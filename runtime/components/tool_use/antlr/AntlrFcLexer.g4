@@ -7,6 +7,10 @@ OPEN_BRACKET : '[';
 CLOSE_BRACKET : ']';
 COMMA : ',';
 COLON : ':';
+// Spreads the keys of a referenced object into the arguments, as in **$base.
+SPREAD : '**';
+// Starts the confidence score some models append to a call, as in @0.87.
+AT : '@';
 
 // Literals
 BOOLEAN : 'true' | 'false';
@@ -15,14 +19,53 @@ NULL_LITERAL : 'null';
 // Number: Integer and floating-point, including exponents
 NUMBER : '-'? INT ( FRAC | EXP )? | '-'? FRAC | '-'? EXP ;
 
-fragment INT : '0' | [1-9] [0-9]*;
-fragment FRAC : '.' [0-9]+;
+// Leading zeros and underscore digit separators are lexed as part of the
+// number so that the parser can apply its policies to them. Exponents take
+// no separators, so that a key such as e5_0 is not lexed as a number.
+fragment INT : SEPARATED_DIGITS;
+fragment FRAC : '.' SEPARATED_DIGITS;
 fragment EXP : [eE] [+-]? [0-9]+;
+fragment SEPARATED_DIGITS : [0-9] [0-9_]*;
+
+// C99 hexadecimal floats such as 0x1.8p3. Malformed ones, such as 0x1.8p,
+// are lexed too so that the parser can report them with their text.
+HEX_FLOAT : '-'? '0' [xX] [0-9a-fA-F]* ( '.' [0-9a-fA-F]* )? ( [pP] [+-]? [0-9]* )? ;
 
 ESCAPED_STRING : '<escape>' .*? '<escape>';
 
+// Shell-style quoted strings: '...' holds its text literally and "..."
+// takes backslash escapes. Only the Rust parser accepts them, with
+// shell_quoting.
+QUOTED_STRING : '\'' ~'\''* '\'' | '"' ( '\\' . | ~["\\] )* '"';
+
+// A reference to the result of an earlier call, such as $step1.
+REFERENCE : '$' [a-zA-Z_] [a-zA-Z_0-9]*;
+
+// ISO-8601 timestamps such as 2025-01-02T03:04:05Z.
+TIMESTAMP
+    : DIGIT DIGIT DIGIT DIGIT '-' DIGIT DIGIT '-' DIGIT DIGIT
+      'T' DIGIT DIGIT ':' DIGIT DIGIT ':' DIGIT DIGIT ( '.' DIGITS )?
+      ( 'Z' | [+-] DIGIT DIGIT ':' DIGIT DIGIT )
+    ;
+
+fragment DIGIT : [0-9];
+fragment DIGITS : [0-9]+;
+
 CALL : 'call';
 ID : [a-zA-Z_] [a-zA-Z_0-9]*;
 
+// ISO-8601 durations such as PT1H30M. They come after ID, so that a key or
+// name such as P1D stays an ID; the parsers take an ID in value position
+// that has the shape of a duration as one. Only a duration with a fraction,
+// such as PT0.5S, which no ID matches, is lexed as DURATION.
+DURATION
+    : 'P' ( DIGITS [YMWD] )+ ( 'T' TIME_PART+ )?
+    | 'P' 'T' TIME_PART+
+    ;
+fragment TIME_PART : DIGITS ( '.' DIGITS )? [HMS];
+// A key such as user.name, which only the Rust parser expands into nested
+// objects.
+DOTTED_ID : ID ( '.' ID )+;
+
 // Whitespace: Skipped
 WS : [ \t\n\r]+ -> skip;
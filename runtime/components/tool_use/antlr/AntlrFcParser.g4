@@ -4,19 +4,38 @@ options {
     tokenVocab = AntlrFcLexer;
 }
 
-start : functionCall EOF;
+start : functionCalls EOF;
 
-functionCall: CALL COLON ID object;
+// Top-level calls may be separated by commas or just whitespace.
+functionCalls : functionCall ( COMMA? functionCall )*;
+
+// Positional arguments in an array are read by the Rust parser as
+// alternating keys and values. Only the Rust parser reads the trailing
+// confidence score.
+functionCall: CALL COLON ID ( object | array ) ( AT NUMBER )?;
 
 object : OPEN_BRACE ( pair (COMMA pair)* )? CLOSE_BRACE;
 
-pair : ID COLON value;
+// A spread is only meaningful among the arguments of a call, which the
+// parsers check.
+pair : ( ID | DOTTED_ID ) COLON value | SPREAD REFERENCE;
 
 value
     : ESCAPED_STRING
+    | QUOTED_STRING
     | NUMBER
+    | HEX_FLOAT
     | BOOLEAN
     | NULL_LITERAL
+    | DURATION
+    | TIMESTAMP
+    | REFERENCE
+    // A bare word, which is a value only where the parser maps it to a
+    // literal, so that `nil` can stand for null.
+    | ID
+    // A nested call whose result is the value, resolved before the call
+    // holding it.
+    | functionCall
     | object
     | array
     ;
@@ -20,6 +20,8 @@
 #include "absl/log/absl_log.h"  // from @com_google_absl
 #include "absl/status/status.h"  // from @com_google_absl
 #include "absl/status/statusor.h"  // from @com_google_absl
+#include "absl/strings/ascii.h"  // from @com_google_absl
+#include "absl/strings/match.h"  // from @com_google_absl
 #include "absl/strings/numbers.h"  // from @com_google_absl
 #include "absl/strings/str_cat.h"  // from @com_google_absl
 #include "absl/strings/string_view.h"  // from @com_google_absl
@@ -45,6 +47,37 @@ absl::string_view StripEscapeTokens(absl::string_view text) {
   return text;
 }
 
+// Returns true if `text` is one or more runs of digits, each followed by one
+// of `units`.
+bool IsUnitParts(absl::string_view text, absl::string_view units) {
+  int digits = 0;
+  for (char c : text) {
+    if (absl::ascii_isdigit(c)) {
+      ++digits;
+    } else if (digits > 0 && absl::StrContains(units, c)) {
+      digits = 0;
+    } else {
+      return false;
+    }
+  }
+  return digits == 0;
+}
+
+// Returns true if `word`, an ID token, has the shape of a duration such as
+// P1D or PT1H30M. The lexer leaves these to the parser, so that they stay
+// usable as keys and names.
+bool IsDuration(absl::string_view word) {
+  if (!absl::ConsumePrefix(&word, "P")) {
+    return false;
+  }
+  const size_t time = word.find('T');
+  if (time == absl::string_view::npos) {
+    return !word.empty() && IsUnitParts(word, "YMWD");
+  }
+  return IsUnitParts(word.substr(0, time), "YMWD") && time + 1 < word.size() &&
+         IsUnitParts(word.substr(time + 1), "HMS");
+}
+
 absl::StatusOr<nlohmann::ordered_json> ParseArray(
     antlr_fc_tool_call_parser::AntlrFcParser::ArrayContext* array_ctx);
 
@@ -62,6 +95,20 @@ absl::StatusOr<nlohmann::ordered_json> ParseValue(
     return nlohmann::ordered_json(
         std::string(StripEscapeTokens(value_ctx->getText())));
   } else if (value_ctx->NUMBER()) {
+    // The lexer accepts leading zeros so that the Rust parser can strip them
+    // on request; here they are rejected as in JSON.
+    const std::string text = value_ctx->getText();
+    if (absl::StrContains(text, '_')) {
+      return absl::InvalidArgumentError(
+          absl::StrCat("Unsupported digit separator: ", text));
+    }
+    absl::string_view digits = text;
+    absl::ConsumePrefix(&digits, "-");
+    if (digits.size() > 1 && digits[0] == '0' &&
+        absl::ascii_isdigit(digits[1])) {
+      return absl::InvalidArgumentError(
+          absl::StrCat("Leading zeros are not allowed: ", text));
+    }
     double double_value;
     if (!absl::SimpleAtod(value_ctx->getText(), &double_value)) {
       return absl::InvalidArgumentError(
@@ -76,6 +123,31 @@ absl::StatusOr<nlohmann::ordered_json> ParseValue(
     return nlohmann::ordered_json(value_ctx->getText() == "true");
   } else if (value_ctx->NULL_LITERAL()) {
     return nlohmann::ordered_json(nullptr);
+  } else if (value_ctx->HEX_FLOAT()) {
+    // Only the Rust parser accepts hexadecimal floats.
+    return absl::InvalidArgumentError(
+        absl::StrCat("Unsupported hex float: ", value_ctx->getText()));
+  } else if (value_ctx->REFERENCE()) {
+    // Only the Rust parser resolves references to earlier results.
+    return absl::InvalidArgumentError(
+        absl::StrCat("Unsupported reference: ", value_ctx->getText()));
+  } else if (value_ctx->DURATION() || value_ctx->TIMESTAMP() ||
+             (value_ctx->ID() && IsDuration(value_ctx->getText()))) {
+    // Only the Rust parser accepts unquoted temporal literals.
+    return absl::InvalidArgumentError(absl::StrCat(
+        "Unquoted temporal literal: ", value_ctx->getText()));
+  } else if (value_ctx->QUOTED_STRING()) {
+    // Only the Rust parser accepts shell-style quoting.
+    return absl::InvalidArgumentError(
+        absl::StrCat("Unsupported quoted string: ", value_ctx->getText()));
+  } else if (value_ctx->functionCall()) {
+    // Only the Rust parser accepts nested calls.
+    return absl::InvalidArgumentError(
+        absl::StrCat("Unsupported nested call: ", value_ctx->getText()));
+  } else if (value_ctx->ID()) {
+    // Only the Rust parser maps bare words to literals.
+    return absl::InvalidArgumentError(
+        absl::StrCat("Unsupported bare word: ", value_ctx->getText()));
   } else {
     // This cannot happen if the grammar is correct.
     return absl::InternalError(
@@ -112,6 +184,16 @@ absl::StatusOr<nlohmann::ordered_json> ParseObject(
 
   for (antlr_fc_tool_call_parser::AntlrFcParser::PairContext* pair_ctx :
        object_ctx->pair()) {
+    if (pair_ctx != nullptr && pair_ctx->SPREAD()) {
+      // Only the Rust parser collects spreads of earlier results.
+      return absl::InvalidArgumentError(
+          absl::StrCat("Unsupported spread: ", pair_ctx->getText()));
+    }
+    if (pair_ctx != nullptr && pair_ctx->DOTTED_ID()) {
+      // Only the Rust parser expands dotted keys into nested objects.
+      return absl::InvalidArgumentError(absl::StrCat(
+          "Unsupported dotted key: ", pair_ctx->DOTTED_ID()->getText()));
+    }
     if (pair_ctx == nullptr || pair_ctx->ID() == nullptr ||
         pair_ctx->value() == nullptr) {
       // This cannot happen if the grammar is correct.
@@ -157,7 +239,16 @@ class FcListener : public antlr_fc_tool_call_parser::AntlrFcParserBaseListener {
       tool_call["name"] = ctx->ID()->getText();
     }
     tool_call["arguments"] = nlohmann::ordered_json::object();
-    if (ctx->ID() != nullptr) {
+    if (ctx->array() != nullptr) {
+      // Only the Rust parser reads positional arguments, as alternating keys
+      // and values.
+      status_ = absl::InvalidArgumentError(
+          absl::StrCat("Unsupported positional arguments: ", ctx->getText()));
+    } else if (ctx->AT() != nullptr) {
+      // Only the Rust parser reads a trailing `@0.87` confidence score.
+      status_ = absl::InvalidArgumentError(
+          absl::StrCat("Unsupported confidence: ", ctx->getText()));
+    } else if (ctx->ID() != nullptr) {
       absl::StatusOr<nlohmann::ordered_json> args = ParseObject(ctx->object());
       if (args.ok()) {
         tool_call["arguments"] = std::move(*args);
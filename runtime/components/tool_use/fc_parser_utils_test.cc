@@ -155,6 +155,26 @@ TEST(FcParserUtilsTest, ParseComplexArguments) {
               }])json")));
 }
 
+TEST(FcParserUtilsTest, ParseCommaSeparatedToolCalls) {
+  EXPECT_THAT(ParseFcExpression(R"(call:a{x:1}, call:b{y:2})"),
+              IsOkAndHolds(nlohmann::ordered_json::parse(R"json([{
+                "name": "a",
+                "arguments": {
+                  "x": 1
+                }
+              }, {
+                "name": "b",
+                "arguments": {
+                  "y": 2
+                }
+              }])json")));
+}
+
+TEST(FcParserUtilsTest, TopLevelArrayOfToolCalls) {
+  EXPECT_THAT(ParseFcExpression(R"([call:a{x:1}, call:b{y:2}])"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
 TEST(FcParserUtilsTest, InvalidToolCallSyntax) {
   // Extra comma in the argument list.
   EXPECT_THAT(ParseFcExpression(R"(call:bad_tool_call{x:1,})"),
@@ -186,6 +206,71 @@ TEST(FcParserUtilsTest, NoCallPrefix) {
               StatusIs(absl::StatusCode::kInvalidArgument));
 }
 
+TEST(FcParserUtilsTest, LeadingZerosInNumber) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo{a:007})"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, UnquotedDuration) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo{every:PT1H30M})"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, DurationShapedKeyAndName) {
+  EXPECT_THAT(ParseFcExpression(R"(call:PT1H{P1D:1})"),
+              IsOkAndHolds(nlohmann::ordered_json::parse(R"json([{
+                "name": "PT1H",
+                "arguments": {
+                  "P1D": 1
+                }
+              }])json")));
+}
+
+TEST(FcParserUtilsTest, HexFloat) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo{x:0x1.8p3})"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, Reference) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo{input:$step1})"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, Spread) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo{**$base,x:1})"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, DottedKey) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo{user.name:1})"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, PositionalArguments) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo[<escape>a<escape>,1])"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, Confidence) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo{a:1} @0.87)"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, DigitSeparator) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo{count:1_000})"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, BareWord) {
+  EXPECT_THAT(ParseFcExpression(R"(call:foo{x:nil})"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
+TEST(FcParserUtilsTest, NestedCall) {
+  EXPECT_THAT(ParseFcExpression(R"(call:outer{inner:call:search{q:1}})"),
+              StatusIs(absl::StatusCode::kInvalidArgument));
+}
+
 TEST(FcParserUtilsTest, UnescapedString) {
   EXPECT_THAT(ParseFcExpression(R"(call:print{text:"hello"})"),
               StatusIs(absl::StatusCode::kInvalidArgument));
@@ -17,8 +17,8 @@
 #include <cstddef>
 #include <exception>
 #include <string>
-#include <utility>
 
+#include "absl/strings/escaping.h"  // from @com_google_absl
 #include "absl/strings/string_view.h"  // from @com_google_absl
 #include "ANTLRErrorListener.h"
 #include "Parser.h"
@@ -35,6 +35,7 @@ namespace {
 
 nlohmann::ordered_json StructToJson(const proto::Struct& struct_value);
 nlohmann::ordered_json ListToJson(const proto::ListValue& list_value);
+nlohmann::ordered_json ToolCallToJson(const proto::ToolCall& tool_call);
 
 nlohmann::ordered_json ValueToJson(const proto::Value& value) {
   switch (value.kind_case()) {
@@ -42,6 +43,8 @@ nlohmann::ordered_json ValueToJson(const proto::Value& value) {
       return nlohmann::ordered_json();
     case proto::Value::kNumberValue:
       return nlohmann::ordered_json(value.number_value());
+    case proto::Value::kIntValue:
+      return nlohmann::ordered_json(value.int_value());
     case proto::Value::kStringValue:
       return nlohmann::ordered_json(value.string_value());
     case proto::Value::kBoolValue:
@@ -50,6 +53,13 @@ nlohmann::ordered_json ValueToJson(const proto::Value& value) {
       return StructToJson(value.struct_value());
     case proto::Value::kListValue:
       return ListToJson(value.list_value());
+    case proto::Value::kReferenceValue:
+      return nlohmann::ordered_json({{"$ref", value.reference_value()}});
+    case proto::Value::kBytesValue:
+      return nlohmann::ordered_json(absl::Base64Escape(value.bytes_value()));
+    case proto::Value::kCallValue:
+      return nlohmann::ordered_json(
+          {{"$call", ToolCallToJson(value.call_value())}});
     default:
       return nlohmann::ordered_json();
   }
@@ -71,6 +81,25 @@ nlohmann::ordered_json ListToJson(const proto::ListValue& list_value) {
   return list_json;
 }
 
+nlohmann::ordered_json ToolCallToJson(const proto::ToolCall& tool_call) {
+  nlohmann::ordered_json tool_call_json = nlohmann::ordered_json::object();
+  tool_call_json["name"] = tool_call.name();
+  tool_call_json["arguments"] = StructToJson(tool_call.arguments());
+  for (const std::string& name : tool_call.spread_refs()) {
+    tool_call_json["spread_refs"].push_back(name);
+  }
+  for (const std::string& comment : tool_call.comments()) {
+    tool_call_json["comments"].push_back(comment);
+  }
+  if (tool_call.has_confidence()) {
+    tool_call_json["confidence"] = tool_call.confidence();
+  }
+  if (!tool_call.dispatch_verb().empty()) {
+    tool_call_json["dispatch_verb"] = tool_call.dispatch_verb();
+  }
+  return tool_call_json;
+}
+
 }  // namespace
 
 void DefaultErrorListener::syntaxError(antlr4::Recognizer* recognizer,
@@ -114,13 +143,7 @@ absl::string_view StripQuotes(absl::string_view text) {
 nlohmann::ordered_json ToolCallsToJson(const proto::ToolCalls& tool_calls) {
   nlohmann::ordered_json tool_calls_json = nlohmann::ordered_json::array();
   for (const proto::ToolCall& tool_call : tool_calls.tool_calls()) {
-    nlohmann::ordered_json tool_call_json = nlohmann::ordered_json::object();
-    tool_call_json["name"] = tool_call.name();
-    tool_call_json["arguments"] = nlohmann::ordered_json::object();
-    for (const auto& field : tool_call.arguments().fields()) {
-      tool_call_json["arguments"][field.name()] = ValueToJson(field.value());
-    }
-    tool_calls_json.push_back(std::move(tool_call_json));
+    tool_calls_json.push_back(ToolCallToJson(tool_call));
   }
   return tool_calls_json;
 }
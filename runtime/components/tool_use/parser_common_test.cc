@@ -16,10 +16,13 @@
 
 #include <gmock/gmock.h>
 #include <gtest/gtest.h>
+#include "nlohmann/json.hpp"  // from @nlohmann_json
+#include "runtime/components/tool_use/proto/tool_call.pb.h"
 
 namespace {
 
 using ::litert::lm::StripQuotes;
+using ::litert::lm::ToolCallsToJson;
 using ::testing::Eq;
 
 TEST(ParserCommonTest, StripQuotes) {
@@ -39,4 +42,50 @@ TEST(ParserCommonTest, StripQuotes) {
   EXPECT_THAT(StripQuotes("''"), Eq(""));
 }
 
+TEST(ParserCommonTest, ToolCallsToJsonConvertsNestedCalls) {
+  litert::lm::proto::ToolCalls tool_calls;
+  litert::lm::proto::ToolCall* tool_call = tool_calls.add_tool_calls();
+  tool_call->set_name("f");
+  litert::lm::proto::Field* field =
+      tool_call->mutable_arguments()->add_fields();
+  field->set_name("n");
+  litert::lm::proto::ToolCall* nested =
+      field->mutable_value()->mutable_call_value();
+  nested->set_name("g");
+  litert::lm::proto::Field* nested_field =
+      nested->mutable_arguments()->add_fields();
+  nested_field->set_name("x");
+  nested_field->mutable_value()->set_reference_value("y");
+
+  EXPECT_THAT(ToolCallsToJson(tool_calls),
+              Eq(nlohmann::ordered_json::parse(R"json([{
+                "name": "f",
+                "arguments": {
+                  "n": {"$call": {
+                    "name": "g",
+                    "arguments": {"x": {"$ref": "y"}}
+                  }}
+                }
+              }])json")));
+}
+
+TEST(ParserCommonTest, ToolCallsToJsonKeepsCallFields) {
+  litert::lm::proto::ToolCalls tool_calls;
+  litert::lm::proto::ToolCall* tool_call = tool_calls.add_tool_calls();
+  tool_call->set_name("f");
+  tool_call->add_spread_refs("base");
+  tool_call->add_comments("// first");
+  tool_call->set_confidence(0.5);
+  litert::lm::proto::ToolCall* verb_call = tool_calls.add_tool_calls();
+  verb_call->set_name("g");
+  verb_call->set_dispatch_verb("INVOKE");
+
+  EXPECT_THAT(ToolCallsToJson(tool_calls),
+              Eq(nlohmann::ordered_json::parse(R"json([
+                {"name": "f", "arguments": {}, "spread_refs": ["base"],
+                 "comments": ["// first"], "confidence": 0.5},
+                {"name": "g", "arguments": {}, "dispatch_verb": "INVOKE"}
+              ])json")));
+}
+
 }  // namespace
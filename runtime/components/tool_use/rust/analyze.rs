@@ -0,0 +1,232 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reports which non-strict forms an FC expression relies on, for migrating
+//! prompts toward the strict format. The scan is purely textual and never
+//! fails, so it also covers forms the parser does not accept at all.
+
+use crate::scan::{find_call_spans, ESCAPE};
+use crate::ParseOptions;
+
+/// The non-strict forms found by [`analyze`]. A strict parse accepts the text
+/// only if every flag is false.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeatureUsage {
+    /// Text before the first call, which needs scan_mode.
+    pub surrounding_prose: bool,
+    /// Text after the last call, which needs TrailingInputPolicy::Ignore.
+    pub trailing_text: bool,
+    /// A backslash-newline, which needs line_continuations.
+    pub line_continuations: bool,
+    /// A `'...'` string, which needs shell_quoting.
+    pub single_quotes: bool,
+    /// A `"..."` string, which needs shell_quoting.
+    pub double_quotes: bool,
+    /// A comma right before `}` or `]`. Never accepted.
+    pub trailing_commas: bool,
+    /// A `//` or `/* */` comment, which needs capture_comments.
+    pub comments: bool,
+    /// An unquoted word in value position other than true, false, null or a
+    /// reference, which needs literal_aliases.
+    pub bare_words: bool,
+    /// A number such as `007`, which needs LeadingZeroPolicy::Strip.
+    pub leading_zeros: bool,
+    /// A number such as `1_000`, which needs lenient_numbers.
+    pub digit_separators: bool,
+    /// A number such as `0x1p3`, which needs hex_floats.
+    pub hex_floats: bool,
+    /// An unquoted duration or timestamp, which needs temporal_literals.
+    pub temporal_literals: bool,
+}
+
+// Whether a word at this point is a value rather than a key or a name.
+#[derive(Clone, Copy, PartialEq)]
+enum Position {
+    Key,
+    Name,
+    Value,
+    Other,
+}
+
+fn classify_value(word: &str, usage: &mut FeatureUsage) {
+    if matches!(word, "true" | "false" | "null") || word.starts_with('$') {
+        return;
+    }
+    let unsigned = word.strip_prefix('-').unwrap_or(word);
+    if unsigned.starts_with("0x") || unsigned.starts_with("0X") {
+        usage.hex_floats = true;
+    } else if word.starts_with('P')
+        && word[1..].starts_with(|c: char| c.is_ascii_digit() || c == 'T')
+        || word.starts_with(|c: char| c.is_ascii_digit())
+            && word.contains('T')
+            && word.contains(':')
+    {
+        usage.temporal_literals = true;
+    } else if unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        let int_digits = unsigned.split(['.', 'e', 'E']).next().unwrap_or("");
+        if int_digits.len() > 1 && int_digits.starts_with('0') {
+            usage.leading_zeros = true;
+        }
+        if word.contains('_') {
+            usage.digit_separators = true;
+        }
+    } else {
+        usage.bare_words = true;
+    }
+}
+
+/// Scans `text` for the non-strict forms in [`FeatureUsage`] without parsing
+/// it, so no option has to be enabled to find out what an input needs.
+pub fn analyze(text: &str) -> FeatureUsage {
+    let spans = find_call_spans(text, &ParseOptions::lenient());
+    let mut usage = FeatureUsage {
+        surrounding_prose: spans
+            .first()
+            .is_some_and(|(start, _)| !text[..*start].trim().is_empty()),
+        trailing_text: spans.last().is_some_and(|(_, end)| !text[*end..].trim().is_empty()),
+        ..Default::default()
+    };
+    let bytes = text.as_bytes();
+    let mut brackets = Vec::new();
+    let mut position = Position::Other;
+    let mut after_comma = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &text[i..];
+        let b = bytes[i];
+        if rest.starts_with(ESCAPE) {
+            let body = i + ESCAPE.len();
+            i = text[body..].find(ESCAPE).map_or(text.len(), |len| body + len + ESCAPE.len());
+            position = Position::Other;
+        } else if rest.starts_with("//") {
+            usage.comments = true;
+            i = rest.find('\n').map_or(text.len(), |len| i + len);
+            continue;
+        } else if rest.starts_with("/*") {
+            usage.comments = true;
+            i = rest.find("*/").map_or(text.len(), |len| i + len + 2);
+            continue;
+        } else if rest.starts_with("\\\n") || rest.starts_with("\\\r") {
+            usage.line_continuations = true;
+            i += 2;
+            continue;
+        } else if b == b'\'' || b == b'"' {
+            if b == b'\'' {
+                usage.single_quotes = true;
+            } else {
+                usage.double_quotes = true;
+            }
+            i = rest[1..].find(b as char).map_or(text.len(), |len| i + len + 2);
+            position = Position::Other;
+        } else if b.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        } else if matches!(b, b'{' | b'[') {
+            brackets.push(b);
+            position = if b == b'{' { Position::Key } else { Position::Value };
+            i += 1;
+        } else if matches!(b, b'}' | b']') {
+            if after_comma {
+                usage.trailing_commas = true;
+            }
+            brackets.pop();
+            position = Position::Other;
+            i += 1;
+        } else if b == b',' {
+            position = match brackets.last() {
+                Some(b'{') => Position::Key,
+                Some(b'[') => Position::Value,
+                _ => Position::Other,
+            };
+            i += 1;
+            after_comma = true;
+            continue;
+        } else if b == b':' {
+            if position != Position::Name && !brackets.is_empty() {
+                position = Position::Value;
+            }
+            i += 1;
+        } else {
+            // Numbers never contain `:` and keys never start with a digit, so
+            // a word starting with a digit runs on through a timestamp.
+            let starts_with_digit = b.is_ascii_digit();
+            let len = rest
+                .find(|c: char| {
+                    !(c.is_ascii_alphanumeric()
+                        || matches!(c, '_' | '.' | '+' | '-' | '$')
+                        || c == ':' && starts_with_digit)
+                })
+                .unwrap_or(rest.len());
+            // Any other character is skipped on its own.
+            let len = if len == 0 { rest.chars().next().map_or(1, char::len_utf8) } else { len };
+            let word = &rest[..len];
+            match position {
+                _ if word == "call" && brackets.is_empty() => position = Position::Name,
+                Position::Value => {
+                    classify_value(word, &mut usage);
+                    position = Position::Other;
+                }
+                Position::Name => position = Position::Other,
+                _ => {}
+            }
+            i += len;
+        }
+        after_comma = false;
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_trailing_commas_and_single_quotes() {
+        let usage = analyze("call:f{a:'x',b:[1,2,],c:<escape>it's, fine<escape>,}");
+        assert_eq!(
+            usage,
+            FeatureUsage { single_quotes: true, trailing_commas: true, ..Default::default() }
+        );
+
+        let usage = analyze(
+            "Sure: call:f{a:007, b:1_000, c:PT1H, d:2025-01-02T03:04:05Z, e:fast // go\n, f:$x}",
+        );
+        assert!(usage.surrounding_prose && usage.comments && usage.bare_words);
+        assert!(usage.leading_zeros && usage.digit_separators && usage.temporal_literals);
+        assert!(!usage.hex_floats && !usage.single_quotes && !usage.trailing_commas);
+
+        assert_eq!(analyze("call:f{a:1,b:{c:[true,null]}}"), FeatureUsage::default());
+        assert_eq!(
+            analyze("call:f{a:1} call:g{} ¿é?"),
+            FeatureUsage { trailing_text: true, ..Default::default() }
+        );
+    }
+
+    #[test]
+    fn handles_non_ascii_prose_before_a_call() {
+        let usage = analyze("Voilà: call:f{}");
+        assert_eq!(usage, FeatureUsage { surrounding_prose: true, ..Default::default() });
+
+        let usage = analyze("Voilà: call:f{x:'é'} ¿fin?");
+        assert_eq!(
+            usage,
+            FeatureUsage {
+                surrounding_prose: true,
+                trailing_text: true,
+                single_quotes: true,
+                ..Default::default()
+            }
+        );
+    }
+}
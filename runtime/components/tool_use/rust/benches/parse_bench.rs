@@ -0,0 +1,326 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parse throughput benchmarks over representative inputs. Run with
+//! `cargo bench --bench parse_bench` in a Cargo build of fc_parser; BUILD has
+//! no Bazel target for it yet. A run without arguments then checks the
+//! median times of the "parse" group against bench_baseline.json and exits
+//! with an error if one is over its baseline by more than the tolerance, so
+//! CI can flag a regression. Pass `--save-baseline <name>` before a change
+//! and `--baseline <name>` after it to have criterion report the difference
+//! instead. After an intended change in speed, update bench_baseline.json
+//! from the printed medians. The run also prints how many allocations
+//! parsing thousands of repeated strings takes with and without interning.
+
+use criterion::{black_box, criterion_group, Criterion};
+use fc_parser::{
+    parse_borrowed, parse_fc_expression, parse_fc_expression_with_options,
+    parse_fc_expression_with_sink, parse_function_names, ParseOptions, ValueSink,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const BASELINE: &str = include_str!("bench_baseline.json");
+
+// The inputs timed by bench_parse, by benchmark name.
+fn inputs() -> Vec<(&'static str, String)> {
+    // Each object level is three protobuf messages deep, and protobuf caps the
+    // nesting it serializes at 100.
+    let nested = 30;
+    vec![
+        (
+            "flat_call",
+            "call:search{query:<escape>weather in Paris<escape>,limit:5,safe:true,unit:$c}"
+                .to_string(),
+        ),
+        ("nested_call", format!("call:f{{{}x:1{}}}", "a:{".repeat(nested), "}".repeat(nested))),
+        (
+            "large_array",
+            format!(
+                "call:f{{values:[{}]}}",
+                (0..1000).map(|i| format!("{}.5", i)).collect::<Vec<_>>().join(",")
+            ),
+        ),
+        (
+            "multi_call_batch",
+            (0..100)
+                .map(|i| format!("call:tool{}{{id:{},name:<escape>item {}<escape>}}", i, i, i))
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+    ]
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, text) in inputs() {
+        let result = parse_fc_expression(&text);
+        assert!(result.is_ok, "{}: {}", name, result.error);
+        group.bench_function(name, |b| b.iter(|| parse_fc_expression(black_box(&text))));
+    }
+    group.finish();
+}
+
+// A call simple enough for ParseOptions::fast_path, parsed with and without
+// it.
+fn bench_fast_path(c: &mut Criterion) {
+    let text = "call:search{query:<escape>weather in Paris<escape>,limit:5,safe:true,score:-0.25}";
+    let mut group = c.benchmark_group("fast_path");
+    for (name, options) in [
+        ("full_parser", ParseOptions::default()),
+        ("fast_path", ParseOptions { fast_path: true, ..Default::default() }),
+    ] {
+        assert!(parse_fc_expression_with_options(text, &options).is_ok, "{}", name);
+        group.bench_function(name, |b| {
+            b.iter(|| parse_fc_expression_with_options(black_box(text), &options))
+        });
+    }
+    group.finish();
+}
+
+// Long string arguments, which parse_borrowed leaves in the input instead
+// of copying into protos.
+fn bench_borrowed(c: &mut Criterion) {
+    let text = format!(
+        "call:f{{{}}}",
+        (0..50)
+            .map(|i| format!("k{}:<escape>{}<escape>", i, "v".repeat(200)))
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let mut group = c.benchmark_group("borrowed");
+    group.bench_function("proto", |b| b.iter(|| parse_fc_expression(black_box(&text))));
+    group.bench_function("borrowed", |b| b.iter(|| parse_borrowed(black_box(&text)).unwrap()));
+    group.finish();
+}
+
+// Calls with nested arguments, read in full and by parse_function_names,
+// which skips the arguments.
+fn bench_function_names(c: &mut Criterion) {
+    let call = format!(
+        "call:f{{{}}}",
+        (0..20)
+            .map(|i| format!("k{}:{{a:[1,2,3],b:<escape>v<escape>}}", i))
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let text = vec![call; 10].join(",");
+    let mut group = c.benchmark_group("function_names");
+    group.bench_function("full_parse", |b| b.iter(|| parse_fc_expression(black_box(&text))));
+    group.bench_function("names_only", |b| {
+        b.iter(|| parse_function_names(black_box(&text)).unwrap())
+    });
+    group.finish();
+}
+
+// The system allocator, counting the allocations it makes.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Collects the string values of a parse, copying each one or, with `interned`
+// set, sharing one copy between equal values.
+#[derive(Default)]
+struct StringSink {
+    interned: Option<HashMap<String, Rc<str>>>,
+    values: Vec<Rc<str>>,
+}
+
+impl ValueSink for StringSink {
+    type Value = Option<Rc<str>>;
+    type Object = ();
+    type Array = ();
+
+    fn begin_object(&mut self) {}
+    fn push_field(&mut self, _object: &mut (), _key: String, _value: Option<Rc<str>>) {}
+    fn end_object(&mut self, _object: ()) -> Option<Rc<str>> {
+        None
+    }
+    fn begin_array(&mut self) {}
+    fn push_element(&mut self, _array: &mut (), value: Option<Rc<str>>) {
+        self.values.extend(value);
+    }
+    fn end_array(&mut self, _array: ()) -> Option<Rc<str>> {
+        None
+    }
+    fn scalar_string(&mut self, s: &str) -> Option<Rc<str>> {
+        let Some(strings) = &mut self.interned else {
+            return Some(Rc::from(s));
+        };
+        if let Some(value) = strings.get(s) {
+            return Some(value.clone());
+        }
+        let value: Rc<str> = Rc::from(s);
+        strings.insert(s.to_string(), value.clone());
+        Some(value)
+    }
+    fn scalar_number(&mut self, _n: f64) -> Option<Rc<str>> {
+        None
+    }
+    fn scalar_bool(&mut self, _b: bool) -> Option<Rc<str>> {
+        None
+    }
+    fn scalar_null(&mut self) -> Option<Rc<str>> {
+        None
+    }
+    fn function_call(&mut self, _name: String, _arguments: Option<()>) {}
+}
+
+// An array of thousands of strings with only two distinct values, as in a
+// batch passing the same path or enum over and over.
+fn repeated_strings() -> String {
+    let elements: Vec<&str> =
+        (0..2000).map(|i| ["<escape>/tmp/a<escape>", "<escape>ENUM_B<escape>"][i % 2]).collect();
+    format!("call:f{{paths:[{}]}}", elements.join(","))
+}
+
+fn parse_strings(text: &str, interned: bool) -> StringSink {
+    let sink = StringSink { interned: interned.then(HashMap::new), ..Default::default() };
+    parse_fc_expression_with_sink(text, &ParseOptions::default(), sink).unwrap()
+}
+
+fn bench_interning(c: &mut Criterion) {
+    let text = repeated_strings();
+    let mut group = c.benchmark_group("interning");
+    group.bench_function("copied", |b| b.iter(|| parse_strings(black_box(&text), false)));
+    group.bench_function("interned", |b| b.iter(|| parse_strings(black_box(&text), true)));
+    group.finish();
+}
+
+// Prints the allocations one parse of repeated_strings makes with and
+// without interning.
+fn report_interning_allocations() {
+    let text = repeated_strings();
+    for interned in [false, true] {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let sink = black_box(parse_strings(&text, interned));
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!(
+            "interning {}: {} allocations for {} strings",
+            if interned { "on" } else { "off" },
+            allocations,
+            sink.values.len()
+        );
+    }
+}
+
+// Fails if `nanos`, the median time of one parse of the input `name`, is
+// more than the baseline's tolerance above its recorded time.
+fn check_against_baseline(baseline: &str, name: &str, nanos: f64) -> Result<(), String> {
+    let baseline: serde_json::Value =
+        serde_json::from_str(baseline).map_err(|e| format!("Invalid baseline: {}", e))?;
+    let tolerance =
+        baseline["tolerance"].as_f64().ok_or_else(|| "Baseline has no tolerance".to_string())?;
+    let expected = baseline["nanos_per_parse"][name]
+        .as_f64()
+        .ok_or_else(|| format!("Baseline has no timing for {}", name))?;
+    let limit = expected * (1.0 + tolerance);
+    if nanos > limit {
+        return Err(format!(
+            "{} took {:.0}ns per parse, over the {:.0}ns baseline by more than {:.0}%",
+            name,
+            nanos,
+            expected,
+            tolerance * 100.0
+        ));
+    }
+    Ok(())
+}
+
+// The directory criterion writes its results to, found the way criterion
+// finds it: cargo runs the bench from target/<profile>/deps.
+fn criterion_dir() -> PathBuf {
+    if let Some(home) = std::env::var_os("CRITERION_HOME") {
+        return home.into();
+    }
+    let target = match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(target) => PathBuf::from(target),
+        None => std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.ancestors().nth(3).map(Path::to_path_buf))
+            .unwrap_or_else(|| "target".into()),
+    };
+    target.join("criterion")
+}
+
+// Reads the median nanoseconds per iteration of the latest run of `name` in
+// the "parse" group from criterion's estimates.json.
+fn measured_median(name: &str) -> Result<f64, String> {
+    let path = criterion_dir().join("parse").join(name).join("new").join("estimates.json");
+    let estimates = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let estimates: serde_json::Value = serde_json::from_str(&estimates)
+        .map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+    estimates["median"]["point_estimate"]
+        .as_f64()
+        .ok_or_else(|| format!("{} has no median", path.display()))
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_fast_path,
+    bench_borrowed,
+    bench_function_names,
+    bench_interning
+);
+
+fn main() -> ExitCode {
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+    report_interning_allocations();
+    // Cargo passes --bench; any other argument filters the benchmarks, runs
+    // them as tests or compares against a named baseline instead.
+    if std::env::args().skip(1).any(|arg| arg != "--bench") {
+        return ExitCode::SUCCESS;
+    }
+    let mut failures = Vec::new();
+    for (name, _) in inputs() {
+        match measured_median(name) {
+            Ok(nanos) => {
+                println!("\"{}\": {:.0}", name, nanos);
+                if let Err(e) = check_against_baseline(BASELINE, name, nanos) {
+                    failures.push(e);
+                }
+            }
+            Err(e) => failures.push(e),
+        }
+    }
+    if failures.is_empty() {
+        return ExitCode::SUCCESS;
+    }
+    eprintln!("{}", failures.join("\n"));
+    ExitCode::FAILURE
+}
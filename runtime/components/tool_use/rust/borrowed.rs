@@ -0,0 +1,175 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A parse result whose string values borrow from the input, for consumers
+//! that only read the calls while the original text is still around.
+
+use crate::sink::ValueSink;
+use crate::{parse_fc_expression_with_sink, ParseOptions};
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// An argument value. Strings are slices of the parsed text unless they had
+/// to be rebuilt, in which case they are owned.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BorrowedValue<'a> {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Int(i64),
+    String(Cow<'a, str>),
+    /// A `$name` reference, without the `$`.
+    Reference(String),
+    List(Vec<BorrowedValue<'a>>),
+    /// Fields in the order they were written.
+    Struct(Vec<(String, BorrowedValue<'a>)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BorrowedToolCall<'a> {
+    pub name: String,
+    pub arguments: Vec<(String, BorrowedValue<'a>)>,
+    /// The `**$name` spreads in the arguments, as in ToolCall.spread_refs.
+    pub spread_refs: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BorrowedToolCalls<'a> {
+    pub tool_calls: Vec<BorrowedToolCall<'a>>,
+}
+
+struct BorrowedSink<'a> {
+    text: &'a str,
+    tool_calls: BorrowedToolCalls<'a>,
+}
+
+impl<'a> ValueSink for BorrowedSink<'a> {
+    type Value = BorrowedValue<'a>;
+    type Object = Vec<(String, BorrowedValue<'a>)>;
+    type Array = Vec<BorrowedValue<'a>>;
+
+    fn begin_object(&mut self) -> Self::Object {
+        Vec::new()
+    }
+
+    fn push_field(&mut self, object: &mut Self::Object, key: String, value: Self::Value) {
+        object.push((key, value));
+    }
+
+    fn end_object(&mut self, object: Self::Object) -> Self::Value {
+        BorrowedValue::Struct(object)
+    }
+
+    fn begin_array(&mut self) -> Self::Array {
+        Vec::new()
+    }
+
+    fn push_element(&mut self, array: &mut Self::Array, value: Self::Value) {
+        array.push(value);
+    }
+
+    fn end_array(&mut self, array: Self::Array) -> Self::Value {
+        BorrowedValue::List(array)
+    }
+
+    fn scalar_string(&mut self, s: &str) -> Self::Value {
+        BorrowedValue::String(Cow::Owned(s.to_string()))
+    }
+
+    fn scalar_string_at(&mut self, s: &str, span: Range<usize>) -> Self::Value {
+        match self.text.get(span) {
+            Some(slice) if slice == s => BorrowedValue::String(Cow::Borrowed(slice)),
+            _ => self.scalar_string(s),
+        }
+    }
+
+    fn scalar_number(&mut self, n: f64) -> Self::Value {
+        BorrowedValue::Number(n)
+    }
+
+    fn scalar_int(&mut self, n: i64) -> Self::Value {
+        BorrowedValue::Int(n)
+    }
+
+    fn scalar_bool(&mut self, b: bool) -> Self::Value {
+        BorrowedValue::Bool(b)
+    }
+
+    fn scalar_null(&mut self) -> Self::Value {
+        BorrowedValue::Null
+    }
+
+    fn scalar_reference(&mut self, name: &str) -> Self::Value {
+        BorrowedValue::Reference(name.to_string())
+    }
+
+    fn kind_name(&self, value: &Self::Value) -> Option<&'static str> {
+        Some(match value {
+            BorrowedValue::Null => "null",
+            BorrowedValue::Bool(_) => "bool",
+            BorrowedValue::Number(_) => "number",
+            BorrowedValue::Int(_) => "int",
+            BorrowedValue::String(_) => "string",
+            BorrowedValue::Reference(_) => "reference",
+            BorrowedValue::List(_) => "array",
+            BorrowedValue::Struct(_) => "object",
+        })
+    }
+
+    fn function_call(&mut self, name: String, arguments: Option<Self::Object>) {
+        self.function_call_with_spreads(name, arguments, Vec::new());
+    }
+
+    fn function_call_with_spreads(
+        &mut self,
+        name: String,
+        arguments: Option<Self::Object>,
+        spread_refs: Vec<String>,
+    ) {
+        let arguments = arguments.unwrap_or_default();
+        self.tool_calls.tool_calls.push(BorrowedToolCall { name, arguments, spread_refs });
+    }
+}
+
+/// Parses `text` with the default options into calls whose string values
+/// are slices of `text` rather than copies.
+pub fn parse_borrowed(text: &str) -> Result<BorrowedToolCalls<'_>, String> {
+    let sink = BorrowedSink { text, tool_calls: BorrowedToolCalls::default() };
+    parse_fc_expression_with_sink(text, &ParseOptions::default(), sink).map(|sink| sink.tool_calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_point_into_the_input() {
+        let text = "call:send{to:<escape>ann<escape>,parts:[<escape>héllo<escape>,7]}, call:noop{}";
+        let tool_calls = parse_borrowed(text).unwrap();
+        assert_eq!(tool_calls.tool_calls.len(), 2);
+        assert_eq!(tool_calls.tool_calls[1].arguments, []);
+
+        let arguments = &tool_calls.tool_calls[0].arguments;
+        let BorrowedValue::List(parts) = &arguments[1].1 else { panic!("{:?}", arguments) };
+        assert_eq!(parts[1], BorrowedValue::Number(7.0));
+        let input = text.as_bytes().as_ptr_range();
+        for (value, expected) in [(&arguments[0].1, "ann"), (&parts[0], "héllo")] {
+            let BorrowedValue::String(Cow::Borrowed(s)) = value else { panic!("{:?}", value) };
+            assert_eq!(*s, expected);
+            assert!(input.contains(&s.as_ptr()));
+        }
+
+        assert!(parse_borrowed("call:f{a:}").is_err());
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A `futures::Stream` adapter that turns a stream of output chunks into a
+//! stream of completed tool calls, for async servers that dispatch each call
+//! as soon as the model has finished writing it.
+
+use crate::scan::{is_id_char, match_header, Header, ESCAPE};
+use crate::{parse_with_options, ParseOptions};
+use futures::{Stream, StreamExt};
+use std::collections::VecDeque;
+use tool_call_rust_proto::ToolCall;
+
+/// The longest call, in bytes, [`tool_call_stream`] buffers before giving up
+/// on it.
+pub const MAX_STREAMED_CALL_BYTES: usize = 1 << 20;
+
+// The call the assembler is in the middle of.
+struct OpenCall {
+    start: usize,
+    // The closing brackets still expected, innermost last.
+    stack: Vec<u8>,
+    // Set once the call outgrew the limit; its text is then dropped as it
+    // arrives rather than buffered.
+    too_long: bool,
+}
+
+// Buffers chunks and cuts each complete `call:name{...}` out of the buffer.
+// Every byte is scanned once: `pos` is where scanning resumes, and text
+// before a call is dropped as soon as it is known not to start one.
+struct CallAssembler {
+    buffer: String,
+    pos: usize,
+    // Whether the byte just before the buffer, long dropped, was an
+    // identifier character, so that `xcall:f{}` is not taken for a call.
+    after_id_char: bool,
+    in_escape: bool,
+    call: Option<OpenCall>,
+    max_call_bytes: usize,
+}
+
+impl Default for CallAssembler {
+    fn default() -> Self {
+        CallAssembler {
+            buffer: String::new(),
+            pos: 0,
+            after_id_char: false,
+            in_escape: false,
+            call: None,
+            max_call_bytes: MAX_STREAMED_CALL_BYTES,
+        }
+    }
+}
+
+impl CallAssembler {
+    fn feed(&mut self, chunk: &str, calls: &mut VecDeque<Result<ToolCall, String>>) {
+        self.buffer.push_str(chunk);
+        self.scan(calls);
+        match &mut self.call {
+            None => self.drop_before(self.pos),
+            Some(call)
+                if !call.too_long && self.buffer.len() - call.start > self.max_call_bytes =>
+            {
+                call.too_long = true;
+                calls
+                    .push_back(Err(format!("Tool call longer than {} bytes", self.max_call_bytes)));
+                self.drop_before(self.pos);
+            }
+            Some(call) if call.too_long => self.drop_before(self.pos),
+            Some(_) => {}
+        }
+    }
+
+    // Scans from `pos` until the buffer runs out or a header or escape
+    // tag is cut off at its end, parsing every call that closes.
+    fn scan(&mut self, calls: &mut VecDeque<Result<ToolCall, String>>) {
+        let escape = ESCAPE.as_bytes();
+        while self.pos < self.buffer.len() {
+            let bytes = self.buffer.as_bytes();
+            let i = self.pos;
+            if self.in_escape {
+                match bytes[i..].windows(escape.len()).position(|window| window == escape) {
+                    Some(offset) => {
+                        self.in_escape = false;
+                        self.pos = i + offset + escape.len();
+                        continue;
+                    }
+                    None => {
+                        self.pos = i.max(bytes.len().saturating_sub(escape.len() - 1));
+                        return;
+                    }
+                }
+            }
+            if bytes[i] == b'<' {
+                if bytes[i..].starts_with(escape) {
+                    self.in_escape = true;
+                    self.pos = i + escape.len();
+                    continue;
+                }
+                if escape.starts_with(&bytes[i..]) {
+                    return;
+                }
+            }
+            let Some(call) = &mut self.call else {
+                let at_word_start =
+                    if i == 0 { !self.after_id_char } else { !is_id_char(bytes[i - 1]) };
+                if at_word_start && bytes[i] == b'c' {
+                    match match_header(bytes, i) {
+                        Header::Open(open) => {
+                            self.call =
+                                Some(OpenCall { start: i, stack: Vec::new(), too_long: false });
+                            self.pos = open;
+                            continue;
+                        }
+                        Header::Partial if bytes.len() - i <= self.max_call_bytes => return,
+                        Header::Partial | Header::NotACall => {}
+                    }
+                }
+                self.pos += 1;
+                continue;
+            };
+            match bytes[i] {
+                b'{' => call.stack.push(b'}'),
+                b'[' => call.stack.push(b']'),
+                b'}' | b']' if call.stack.last() == Some(&bytes[i]) => {
+                    call.stack.pop();
+                }
+                b'}' | b']' => {
+                    // Brackets that do not balance: not a call after all, so
+                    // scanning goes on from just past where it seemed to start.
+                    self.pos = if call.too_long { i + 1 } else { call.start + 1 };
+                    self.call = None;
+                    continue;
+                }
+                _ => {}
+            }
+            self.pos += 1;
+            if call.stack.is_empty() {
+                if !call.too_long {
+                    let text = &self.buffer[call.start..self.pos];
+                    match parse_with_options(text, &ParseOptions::default()) {
+                        Ok((tool_calls, _)) => calls
+                            .extend(tool_calls.tool_calls().iter().map(|call| Ok(call.to_owned()))),
+                        Err(e) => calls.push_back(Err(e.message)),
+                    }
+                }
+                self.call = None;
+                self.drop_before(self.pos);
+            }
+        }
+    }
+
+    // Drops the buffer before `offset`, or before the start of the character
+    // holding it.
+    fn drop_before(&mut self, mut offset: usize) {
+        while !self.buffer.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        if offset == 0 {
+            return;
+        }
+        self.after_id_char = is_id_char(self.buffer.as_bytes()[offset - 1]);
+        self.buffer.drain(..offset);
+        self.pos -= offset;
+        if let Some(call) = &mut self.call {
+            call.start = call.start.saturating_sub(offset);
+        }
+    }
+
+    // Text between and after calls is ignored, unless it starts a call that
+    // never finished.
+    fn finish(&mut self, calls: &mut VecDeque<Result<ToolCall, String>>) {
+        if self.call.as_ref().is_some_and(|call| !call.too_long) {
+            calls.push_back(Err("Incomplete tool call at end of stream".to_string()));
+        }
+        *self = CallAssembler { max_call_bytes: self.max_call_bytes, ..Default::default() };
+    }
+}
+
+/// Yields each tool call in `chunks` as soon as its closing brace arrives,
+/// in order. A call that fails to parse yields an error and the stream moves
+/// on to the next one; a call still open when `chunks` ends yields a final
+/// error. Text outside calls is not kept, and a call longer than
+/// [`MAX_STREAMED_CALL_BYTES`] yields an error and is skipped, so memory
+/// stays bounded however long the stream runs.
+pub fn tool_call_stream<S>(chunks: S) -> impl Stream<Item = Result<ToolCall, String>>
+where
+    S: Stream<Item = String> + Unpin,
+{
+    let state = (chunks, CallAssembler::default(), VecDeque::new(), false);
+    futures::stream::unfold(state, |(mut chunks, mut assembler, mut calls, mut done)| async move {
+        loop {
+            if let Some(call) = calls.pop_front() {
+                return Some((call, (chunks, assembler, calls, done)));
+            }
+            if done {
+                return None;
+            }
+            match chunks.next().await {
+                Some(chunk) => assembler.feed(&chunk, &mut calls),
+                None => {
+                    assembler.finish(&mut calls);
+                    done = true;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::executor::block_on;
+
+    #[test]
+    fn yields_calls_as_chunks_complete_them() {
+        let (sender, receiver) = mpsc::unbounded::<String>();
+        let text = "call:search{q:<escape>a}b<escape>,opts:{n:1}}, call:open{path:[1,2]} done";
+        for chunk in text.as_bytes().chunks(5) {
+            sender.unbounded_send(String::from_utf8(chunk.to_vec()).unwrap()).unwrap();
+        }
+        sender.unbounded_send(" call:late{".to_string()).unwrap();
+        drop(sender);
+
+        let items: Vec<_> = block_on(tool_call_stream(receiver).collect());
+        assert_eq!(items.len(), 3);
+        let names: Vec<String> =
+            items[..2].iter().map(|item| item.as_ref().unwrap().name().to_string()).collect();
+        assert_eq!(names, ["search", "open"]);
+        let q = items[0].as_ref().unwrap().arguments().fields().get(0).unwrap().value();
+        assert_eq!(q.string_value().to_string(), "a}b");
+        assert_eq!(items[2].as_ref().unwrap_err(), "Incomplete tool call at end of stream");
+    }
+
+    #[test]
+    fn drops_prose_and_keeps_only_what_may_start_a_call() {
+        let mut assembler = CallAssembler::default();
+        let mut calls = VecDeque::new();
+        assembler
+            .feed("Sure, I will recall: <escape>call:x{<escape> and xcall:y{} then ", &mut calls);
+        assert!(calls.is_empty());
+        assert_eq!(assembler.buffer, "");
+        assembler.feed("cal", &mut calls);
+        assert_eq!(assembler.buffer, "cal");
+        assembler.feed("l : f", &mut calls);
+        assert_eq!(assembler.buffer, "call : f");
+        assembler.feed("{a:[1,{b:<escape>}]<escape>}]} <esc", &mut calls);
+        assert_eq!(assembler.buffer, "<esc");
+        assembler.feed("ape>", &mut calls);
+        assert_eq!(assembler.buffer, "");
+
+        assert_eq!(calls.len(), 1);
+        let call = calls.pop_front().unwrap().unwrap();
+        assert_eq!(call.name().to_string(), "f");
+    }
+
+    #[test]
+    fn skips_calls_longer_than_the_limit() {
+        let mut assembler = CallAssembler { max_call_bytes: 16, ..Default::default() };
+        let mut calls = VecDeque::new();
+        assembler.feed("call:f{x:<escape>", &mut calls);
+        for _ in 0..100 {
+            assembler.feed("0123456789", &mut calls);
+            assert!(assembler.buffer.len() <= 32, "{}", assembler.buffer.len());
+        }
+        assembler.feed("<escape>} call:g{y:[1]}", &mut calls);
+        assembler.feed(" call:h{z:1", &mut calls);
+        assembler.finish(&mut calls);
+
+        let results: Vec<_> =
+            calls.into_iter().map(|call| call.map(|call| call.name().to_string())).collect();
+        assert_eq!(
+            results,
+            [
+                Err("Tool call longer than 16 bytes".to_string()),
+                Ok("g".to_string()),
+                Err("Incomplete tool call at end of stream".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,525 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CBOR (RFC 8949) encoding of ToolCalls, enabled by the `cbor` feature.
+//!
+//! ToolCalls is encoded as an array of maps, one per call, keyed by the
+//! ToolCall field names. Fields left at their default are omitted. int_value
+//! is written as a CBOR integer and number_value always as a float, in the
+//! shortest width that holds it exactly, so the two kinds survive a round
+//! trip. References are text tagged as identifiers (tag 39), a nested call
+//! is its ToolCall map under tag 0x746f6f6c ("tool", from the first-come
+//! range), and bytes_value is a CBOR byte string.
+
+use protobuf::proto;
+use tool_call_rust_proto::value::KindOneof;
+use tool_call_rust_proto::{
+    Field, ListValue, NullValue, Struct, StructView, ToolCall, ToolCallView, ToolCalls, Value,
+    ValueView,
+};
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+const FALSE: u8 = 0xf4;
+const TRUE: u8 = 0xf5;
+const NULL: u8 = 0xf6;
+const FLOAT16: u8 = 0xf9;
+const FLOAT32: u8 = 0xfa;
+const FLOAT64: u8 = 0xfb;
+
+const TAG_IDENTIFIER: u64 = 39;
+const TAG_TOOL_CALL: u64 = 0x746f6f6c;
+
+// Arrays and maps nested deeper than this are rejected rather than decoded
+// recursively, so that hostile input cannot overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+fn write_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n <= u8::MAX as u64 {
+        out.push(major | 24);
+        out.push(n as u8);
+    } else if n <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn write_text(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_head(out, MAJOR_TEXT, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_int(out: &mut Vec<u8>, n: i64) {
+    if n >= 0 {
+        write_head(out, MAJOR_UNSIGNED, n as u64);
+    } else {
+        write_head(out, MAJOR_NEGATIVE, (-1 - n) as u64);
+    }
+}
+
+// Returns `n` as a half-precision float, if that holds it exactly.
+fn to_half(n: f64) -> Option<u16> {
+    let sign = if n.is_sign_negative() { 0x8000 } else { 0 };
+    if n.is_nan() {
+        // Keeps the payload, which a half cannot hold in general.
+        return None;
+    }
+    if n.is_infinite() {
+        return Some(sign | 0x7c00);
+    }
+    // Subnormal halves, zero included, count in steps of 2^-24.
+    let steps = n.abs() * 2f64.powi(24);
+    if steps < 1024.0 {
+        return (steps.fract() == 0.0).then_some(sign | steps as u16);
+    }
+    let bits = n.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i32 - 1023;
+    let mantissa = bits & ((1 << 52) - 1);
+    if !(-14..=15).contains(&exponent) || mantissa & ((1 << 42) - 1) != 0 {
+        return None;
+    }
+    Some(sign | ((exponent + 15) as u16) << 10 | (mantissa >> 42) as u16)
+}
+
+fn write_number(out: &mut Vec<u8>, n: f64) {
+    if let Some(half) = to_half(n) {
+        out.push(FLOAT16);
+        out.extend_from_slice(&half.to_be_bytes());
+    } else if n as f32 as f64 == n {
+        out.push(FLOAT32);
+        out.extend_from_slice(&(n as f32).to_bits().to_be_bytes());
+    } else {
+        out.push(FLOAT64);
+        out.extend_from_slice(&n.to_bits().to_be_bytes());
+    }
+}
+
+fn write_texts<'a>(out: &mut Vec<u8>, texts: impl ExactSizeIterator<Item = &'a [u8]>) {
+    write_head(out, MAJOR_ARRAY, texts.len() as u64);
+    for text in texts {
+        write_text(out, text);
+    }
+}
+
+fn write_struct(out: &mut Vec<u8>, s: StructView) {
+    write_head(out, MAJOR_MAP, s.fields().len() as u64);
+    for field in s.fields() {
+        write_text(out, field.name().as_bytes());
+        write_value(out, field.value());
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: ValueView) {
+    match value.kind() {
+        KindOneof::NumberValue(n) => write_number(out, n),
+        KindOneof::IntValue(n) => write_int(out, n),
+        KindOneof::StringValue(s) => write_text(out, s.as_bytes()),
+        KindOneof::BytesValue(bytes) => {
+            write_head(out, MAJOR_BYTES, bytes.len() as u64);
+            out.extend_from_slice(bytes);
+        }
+        KindOneof::ReferenceValue(name) => {
+            write_head(out, MAJOR_TAG, TAG_IDENTIFIER);
+            write_text(out, name.as_bytes());
+        }
+        KindOneof::CallValue(call) => {
+            write_head(out, MAJOR_TAG, TAG_TOOL_CALL);
+            write_tool_call(out, call);
+        }
+        KindOneof::BoolValue(b) => out.push(if b { TRUE } else { FALSE }),
+        KindOneof::StructValue(s) => write_struct(out, s),
+        KindOneof::ListValue(l) => {
+            write_head(out, MAJOR_ARRAY, l.values().len() as u64);
+            for element in l.values() {
+                write_value(out, element);
+            }
+        }
+        _ => out.push(NULL),
+    }
+}
+
+fn write_tool_call(out: &mut Vec<u8>, tool_call: ToolCallView) {
+    // Calls without arguments omit the key so that has_arguments() survives
+    // the round trip.
+    let (spread_refs, comments) = (tool_call.spread_refs(), tool_call.comments());
+    let len = 1
+        + usize::from(tool_call.has_arguments())
+        + usize::from(!spread_refs.is_empty())
+        + usize::from(!comments.is_empty())
+        + usize::from(tool_call.has_confidence())
+        + usize::from(!tool_call.dispatch_verb().is_empty());
+    write_head(out, MAJOR_MAP, len as u64);
+    write_text(out, b"name");
+    write_text(out, tool_call.name().as_bytes());
+    if tool_call.has_arguments() {
+        write_text(out, b"arguments");
+        write_struct(out, tool_call.arguments());
+    }
+    if !spread_refs.is_empty() {
+        write_text(out, b"spread_refs");
+        write_texts(out, spread_refs.iter().map(|name| name.as_bytes()));
+    }
+    if !comments.is_empty() {
+        write_text(out, b"comments");
+        write_texts(out, comments.iter().map(|comment| comment.as_bytes()));
+    }
+    if tool_call.has_confidence() {
+        write_text(out, b"confidence");
+        write_number(out, tool_call.confidence());
+    }
+    if !tool_call.dispatch_verb().is_empty() {
+        write_text(out, b"dispatch_verb");
+        write_text(out, tool_call.dispatch_verb().as_bytes());
+    }
+}
+
+/// Encodes `tool_calls` as CBOR.
+pub fn to_cbor(tool_calls: &ToolCalls) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_head(&mut out, MAJOR_ARRAY, tool_calls.tool_calls().len() as u64);
+    for tool_call in tool_calls.tool_calls() {
+        write_tool_call(&mut out, tool_call);
+    }
+    out
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+enum Item {
+    Unsigned(u64),
+    Negative(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(u64),
+    Map(u64),
+    Tag(u64),
+    Bool(bool),
+    Null,
+    Float(f64),
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < n {
+            return Err(format!("Unexpected end of CBOR input at offset {}", self.pos));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn argument(&mut self, info: u8) -> Result<u64, String> {
+        match info {
+            0..=23 => Ok(info as u64),
+            24 => Ok(self.take(1)?[0] as u64),
+            25 => Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64),
+            26 => Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64),
+            27 => Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+            _ => Err(format!("Unsupported CBOR length encoding {} at offset {}", info, self.pos)),
+        }
+    }
+
+    fn item(&mut self) -> Result<Item, String> {
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        match major {
+            MAJOR_UNSIGNED => Ok(Item::Unsigned(self.argument(info)?)),
+            MAJOR_NEGATIVE => Ok(Item::Negative(self.argument(info)?)),
+            MAJOR_BYTES => {
+                let len = self.argument(info)? as usize;
+                Ok(Item::Bytes(self.take(len)?.to_vec()))
+            }
+            MAJOR_TEXT => {
+                let len = self.argument(info)? as usize;
+                let bytes = self.take(len)?;
+                String::from_utf8(bytes.to_vec())
+                    .map(Item::Text)
+                    .map_err(|e| format!("Invalid UTF-8 in CBOR text: {}", e))
+            }
+            MAJOR_ARRAY => Ok(Item::Array(self.argument(info)?)),
+            MAJOR_MAP => Ok(Item::Map(self.argument(info)?)),
+            MAJOR_TAG => Ok(Item::Tag(self.argument(info)?)),
+            MAJOR_SIMPLE => match initial {
+                FALSE => Ok(Item::Bool(false)),
+                TRUE => Ok(Item::Bool(true)),
+                NULL => Ok(Item::Null),
+                FLOAT16 => {
+                    let bits = u16::from_be_bytes(self.take(2)?.try_into().unwrap());
+                    Ok(Item::Float(half_to_f64(bits)))
+                }
+                FLOAT32 => {
+                    let bits = u32::from_be_bytes(self.take(4)?.try_into().unwrap());
+                    Ok(Item::Float(f32::from_bits(bits) as f64))
+                }
+                FLOAT64 => {
+                    let bits = u64::from_be_bytes(self.take(8)?.try_into().unwrap());
+                    Ok(Item::Float(f64::from_bits(bits)))
+                }
+                _ => Err(format!("Unsupported CBOR simple value 0x{:02x}", initial)),
+            },
+            _ => Err(format!("Unsupported CBOR major type {} at offset {}", major, self.pos - 1)),
+        }
+    }
+
+    fn text(&mut self) -> Result<String, String> {
+        match self.item()? {
+            Item::Text(s) => Ok(s),
+            _ => Err(format!("Expected CBOR text before offset {}", self.pos)),
+        }
+    }
+
+    fn texts(&mut self) -> Result<Vec<String>, String> {
+        let Item::Array(len) = self.item()? else {
+            return Err(format!("Expected a CBOR array of text before offset {}", self.pos));
+        };
+        (0..len).map(|_| self.text()).collect()
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        match self.item()? {
+            Item::Float(n) => Ok(n),
+            Item::Unsigned(n) => Ok(n as f64),
+            Item::Negative(n) => Ok(-1.0 - n as f64),
+            _ => Err(format!("Expected a CBOR number before offset {}", self.pos)),
+        }
+    }
+
+    // Counts one more level of nesting, failing past MAX_DEPTH.
+    fn enter(&mut self) -> Result<(), String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!(
+                "CBOR input nests deeper than {} levels at offset {}",
+                MAX_DEPTH, self.pos
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn structure(&mut self, len: u64) -> Result<Struct, String> {
+        self.enter()?;
+        let mut object = Struct::new();
+        for _ in 0..len {
+            let key = self.text()?;
+            let value = self
+                .value()
+                .map_err(|e| format!("Error parsing value for key '{}': {}", key, e))?;
+            let mut field = Field::new();
+            field.set_name(key);
+            field.set_value(value);
+            object.fields_mut().push(field);
+        }
+        self.depth -= 1;
+        Ok(object)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.item()? {
+            Item::Unsigned(n) => i64::try_from(n)
+                .map(|n| proto!(Value { int_value: n }))
+                .map_err(|_| format!("CBOR integer {} does not fit in int_value", n)),
+            Item::Negative(n) => i64::try_from(n)
+                .map(|n| proto!(Value { int_value: -1 - n }))
+                .map_err(|_| format!("CBOR integer -1-{} does not fit in int_value", n)),
+            Item::Float(n) => Ok(proto!(Value { number_value: n })),
+            Item::Text(s) => Ok(proto!(Value { string_value: s })),
+            Item::Bytes(bytes) => Ok(proto!(Value { bytes_value: bytes })),
+            Item::Bool(b) => Ok(proto!(Value { bool_value: b })),
+            Item::Null => Ok(proto!(Value { null_value: NullValue::default() })),
+            Item::Array(len) => {
+                self.enter()?;
+                let mut list_value = ListValue::new();
+                for _ in 0..len {
+                    list_value.values_mut().push(self.value()?);
+                }
+                self.depth -= 1;
+                Ok(proto!(Value { list_value: list_value }))
+            }
+            Item::Map(len) => {
+                let s = self.structure(len)?;
+                Ok(proto!(Value { struct_value: s }))
+            }
+            Item::Tag(TAG_IDENTIFIER) => Ok(proto!(Value { reference_value: self.text()? })),
+            Item::Tag(TAG_TOOL_CALL) => {
+                self.enter()?;
+                let tool_call = self.tool_call()?;
+                self.depth -= 1;
+                Ok(proto!(Value { call_value: tool_call }))
+            }
+            Item::Tag(tag) => Err(format!("Unsupported CBOR tag {}", tag)),
+        }
+    }
+
+    fn tool_call(&mut self) -> Result<ToolCall, String> {
+        let Item::Map(len) = self.item()? else {
+            return Err("Tool call must be a CBOR map".to_string());
+        };
+        let mut tool_call = ToolCall::new();
+        for _ in 0..len {
+            match self.text()?.as_str() {
+                "name" => tool_call.set_name(self.text()?),
+                "arguments" => {
+                    let Item::Map(len) = self.item()? else {
+                        return Err("Tool call arguments must be a CBOR map".to_string());
+                    };
+                    tool_call.set_arguments(self.structure(len)?);
+                }
+                "spread_refs" => tool_call.set_spread_refs(self.texts()?.into_iter()),
+                "comments" => tool_call.set_comments(self.texts()?.into_iter()),
+                "confidence" => tool_call.set_confidence(self.number()?),
+                "dispatch_verb" => tool_call.set_dispatch_verb(self.text()?),
+                other => return Err(format!("Unknown tool call key '{}'", other)),
+            }
+        }
+        Ok(tool_call)
+    }
+}
+
+fn half_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    };
+    sign * magnitude
+}
+
+/// Decodes ToolCalls previously encoded with [`to_cbor`].
+pub fn from_cbor(bytes: &[u8]) -> Result<ToolCalls, String> {
+    let mut decoder = Decoder { bytes, pos: 0, depth: 0 };
+    let Item::Array(len) = decoder.item()? else {
+        return Err("Expected a CBOR array of tool calls".to_string());
+    };
+    let mut tool_calls = ToolCalls::new();
+    for index in 0..len {
+        let tool_call = decoder
+            .tool_call()
+            .map_err(|e| format!("Error parsing tool call at index {}: {}", index, e))?;
+        tool_calls.tool_calls_mut().push(tool_call);
+    }
+    if decoder.pos != bytes.len() {
+        return Err(format!("Trailing bytes after CBOR tool calls at offset {}", decoder.pos));
+    }
+    Ok(tool_calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fc_expression, parse_fc_expression_with_options, ParseOptions};
+    use protobuf::prelude::*;
+    use tool_call_rust_proto::value::KindCase;
+
+    #[test]
+    fn round_trips_nested_calls() {
+        let options =
+            ParseOptions { integer_values: true, nested_calls: true, ..Default::default() };
+        let result = parse_fc_expression_with_options(
+            "call:f{a:1,b:-2.5,c:<escape>hi<escape>,d:[true,null,{e:[]}],f:{g:-0.0,h:1.0},i:-9000000000,r:$x,n:call:g{x:[1]}}",
+            &options,
+        );
+        assert!(result.is_ok, "{}", result.error);
+        let parsed = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+        let mut first = parsed.tool_calls().get(0).unwrap().to_owned();
+        first.set_spread_refs(["base", "extra"].into_iter());
+        first.set_comments(["// first"].into_iter());
+        first.set_confidence(0.87);
+        first.set_dispatch_verb("INVOKE");
+        let mut second = ToolCall::new();
+        second.set_name("g");
+        second.set_confidence(1.0);
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(first);
+        tool_calls.tool_calls_mut().push(second);
+
+        let encoded = to_cbor(&tool_calls);
+        let decoded = from_cbor(&encoded).unwrap();
+        assert_eq!(decoded.serialize().unwrap(), tool_calls.serialize().unwrap());
+        let fields = decoded.tool_calls().get(0).unwrap().arguments().fields();
+        assert_eq!(fields.get(0).unwrap().value().kind_case(), KindCase::IntValue);
+        let h = fields.get(4).unwrap().value().struct_value().fields().get(1).unwrap().value();
+        assert_eq!(h.kind_case(), KindCase::NumberValue);
+    }
+
+    #[test]
+    fn uses_native_cbor_types() {
+        let mut out = Vec::new();
+        write_int(&mut out, 1);
+        write_int(&mut out, -1);
+        write_number(&mut out, 1.0);
+        write_number(&mut out, -0.0);
+        write_number(&mut out, 0.5);
+        write_number(&mut out, 65504.0);
+        write_number(&mut out, 100000.0);
+        write_number(&mut out, 0.1);
+        assert_eq!(
+            out,
+            [
+                0x01, 0x20, 0xf9, 0x3c, 0x00, 0xf9, 0x80, 0x00, 0xf9, 0x38, 0x00, 0xf9, 0x7b, 0xff,
+                0xfa, 0x47, 0xc3, 0x50, 0x00, 0xfb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a,
+            ]
+        );
+        for n in [5.960464477539063e-8, 6.1e-5, 1.5, f64::INFINITY, -f64::INFINITY] {
+            let mut out = Vec::new();
+            write_number(&mut out, n);
+            let mut decoder = Decoder { bytes: &out, pos: 0, depth: 0 };
+            assert_eq!(decoder.number().unwrap(), n);
+        }
+    }
+
+    #[test]
+    fn rejects_input_nested_too_deeply() {
+        let mut encoded = vec![0x81, 0xa2, 0x64];
+        encoded.extend_from_slice(b"name");
+        encoded.extend_from_slice(&[0x61, b'f', 0x69]);
+        encoded.extend_from_slice(b"arguments");
+        encoded.extend_from_slice(&[0xa1, 0x61, b'a']);
+        encoded.extend(std::iter::repeat_n(0x81, 10_000));
+        encoded.push(NULL);
+        let error = from_cbor(&encoded).unwrap_err();
+        assert!(error.contains("nests deeper than 128 levels"), "unexpected error: {}", error);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let result = parse_fc_expression("call:f{a:<escape>hello<escape>}");
+        let tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+        let encoded = to_cbor(&tool_calls);
+        assert!(from_cbor(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hand-written scanner for the common `call:name{key:scalar,...}` shape.
+//!
+//! It accepts exactly the inputs it can parse the same way as the ANTLR
+//! parser and returns None for everything else, so callers fall back to the
+//! full parser for nesting, errors and any token it is unsure about.
+
+use crate::scan::ESCAPE;
+use protobuf::proto;
+use std::collections::HashSet;
+use tool_call_rust_proto::{Field, NullValue, Struct, ToolCall, ToolCalls, Value};
+
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+fn is_id_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+fn is_id_part(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+impl<'a> Scanner<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    // Same character set as the lexer's WS rule.
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Option<()> {
+        self.skip_ws();
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn word(&mut self) -> Option<&'a str> {
+        self.skip_ws();
+        let start = self.pos;
+        if !self.peek().is_some_and(is_id_start) {
+            return None;
+        }
+        while self.peek().is_some_and(is_id_part) {
+            self.pos += 1;
+        }
+        Some(&self.text[start..self.pos])
+    }
+
+    // An ID token. Words the lexer turns into keywords are rejected, as are
+    // words like `e5` that lex as an exponent-only NUMBER.
+    fn id(&mut self) -> Option<&'a str> {
+        match self.word()? {
+            "call" | "true" | "false" | "null" => None,
+            id if id.len() > 1
+                && id.starts_with(['e', 'E'])
+                && id[1..].bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                None
+            }
+            id => Some(id),
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    // Matches the NUMBER lexer rule: '-'? INT (FRAC | EXP)? | '-'? FRAC | '-'? EXP.
+    fn number(&mut self) -> Option<f64> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek()? {
+            b'0' => self.pos += 1,
+            b'1'..=b'9' => {
+                self.digits();
+            }
+            _ => return None,
+        }
+        match self.peek() {
+            Some(b'.') => {
+                self.pos += 1;
+                if self.digits() == 0 {
+                    return None;
+                }
+            }
+            Some(b'e' | b'E') => {
+                self.pos += 1;
+                if matches!(self.peek(), Some(b'+' | b'-')) {
+                    self.pos += 1;
+                }
+                if self.digits() == 0 {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+        // Anything glued to the number would lex as a separate token.
+        if !matches!(self.peek(), None | Some(b' ' | b'\t' | b'\n' | b'\r' | b',' | b'}')) {
+            return None;
+        }
+        let value = self.text[start..self.pos].parse::<f64>().ok()?;
+        value.is_finite().then_some(value)
+    }
+
+    fn scalar(&mut self) -> Option<Value> {
+        self.skip_ws();
+        let rest = &self.text[self.pos..];
+        if rest.starts_with(ESCAPE) {
+            let body = self.pos + ESCAPE.len();
+            let len = self.text[body..].find(ESCAPE)?;
+            self.pos = body + len + ESCAPE.len();
+            return Some(proto!(Value { string_value: &self.text[body..body + len] }));
+        }
+        match self.peek()? {
+            b'-' | b'0'..=b'9' => {
+                let n = self.number()?;
+                Some(proto!(Value { number_value: n }))
+            }
+            _ => match self.word()? {
+                "true" => Some(proto!(Value { bool_value: true })),
+                "false" => Some(proto!(Value { bool_value: false })),
+                "null" => Some(proto!(Value { null_value: NullValue::default() })),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Parses a single call with flat scalar arguments, or returns None if the
+/// text has any other shape.
+pub(crate) fn parse_flat_call(text: &str) -> Option<ToolCalls> {
+    let mut scanner = Scanner { text, pos: 0 };
+    if scanner.word()? != "call" {
+        return None;
+    }
+    scanner.expect(b':')?;
+    let name = scanner.id()?;
+    scanner.expect(b'{')?;
+
+    let mut arguments = Struct::new();
+    let mut seen_keys = HashSet::new();
+    scanner.skip_ws();
+    if scanner.peek() == Some(b'}') {
+        scanner.pos += 1;
+    } else {
+        loop {
+            let key = scanner.id()?;
+            scanner.expect(b':')?;
+            let value = scanner.scalar()?;
+            // Duplicate keys are left to the full parser, which reports them
+            // as warnings.
+            if !seen_keys.insert(key) {
+                return None;
+            }
+            let mut field = Field::new();
+            field.set_name(key);
+            field.set_value(value);
+            arguments.fields_mut().push(field);
+            scanner.skip_ws();
+            match scanner.peek()? {
+                b',' => scanner.pos += 1,
+                b'}' => {
+                    scanner.pos += 1;
+                    break;
+                }
+                _ => return None,
+            }
+        }
+    }
+    scanner.skip_ws();
+    if scanner.pos != text.len() {
+        return None;
+    }
+
+    let mut tool_call = ToolCall::new();
+    tool_call.set_name(name);
+    tool_call.set_arguments(arguments);
+    let mut tool_calls = ToolCalls::new();
+    tool_calls.tool_calls_mut().push(tool_call);
+    Some(tool_calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fc_expression_with_options, ParseOptions};
+
+    // Deterministic generator of flat calls, some of which the fast path has
+    // to hand back to the full parser.
+    fn flat_calls(count: usize) -> Vec<String> {
+        const SCALARS: &[&str] = &[
+            "1",
+            "-0",
+            "0.25",
+            "-12.5",
+            "3e5",
+            "-2E-3",
+            "1e999",
+            "01",
+            "true",
+            "false",
+            "null",
+            "<escape>hi<escape>",
+            "<escape><escape>",
+            "<escape>a, b}<escape>",
+        ];
+        const KEYS: &[&str] = &["a", "b_2", "_c", "call", "truex", "e5", "P1D", "a"];
+        let mut seed: u64 = 0x2545f4914f6cdd1d;
+        let mut next = move |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n as u64) as usize
+        };
+        (0..count)
+            .map(|_| {
+                let pairs: Vec<String> = (0..next(5))
+                    .map(|_| {
+                        let sep = [":", " : ", ":\n"][next(3)];
+                        format!("{}{}{}", KEYS[next(KEYS.len())], sep, SCALARS[next(SCALARS.len())])
+                    })
+                    .collect();
+                format!(
+                    "call:f{}{{{}}}{}",
+                    next(10),
+                    pairs.join([",", " , "][next(2)]),
+                    ["", " ", "x"][next(3)]
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fast_path_matches_full_parser() {
+        let fast = ParseOptions { fast_path: true, ..Default::default() };
+        let full = ParseOptions::default();
+        let mut handled = 0;
+        for text in flat_calls(500) {
+            if parse_flat_call(&text).is_some() {
+                handled += 1;
+            }
+            let fast_result = parse_fc_expression_with_options(&text, &fast);
+            let full_result = parse_fc_expression_with_options(&text, &full);
+            assert_eq!(fast_result.is_ok, full_result.is_ok, "{}", text);
+            assert_eq!(
+                fast_result.serialized_tool_calls, full_result.serialized_tool_calls,
+                "{}",
+                text
+            );
+        }
+        assert!(handled > 100, "fast path only handled {} calls", handled);
+    }
+
+    #[test]
+    fn fast_path_declines_nested_arguments() {
+        assert!(parse_flat_call("call:f{a:{b:1}}").is_none());
+        assert!(parse_flat_call("call:f{a:[1]}").is_none());
+        assert!(parse_flat_call("call:f{a:1}").is_some());
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of C99 hexadecimal floats such as `0x1.8p3` to f64. The lexer
+//! accepts malformed ones too, so the shape is checked here.
+
+// Multiplies `x` by 2^exp in steps, so that no intermediate power of two
+// overflows or underflows.
+fn scale_by_power_of_two(mut x: f64, mut exp: i64) -> f64 {
+    while exp > 1000 {
+        x *= 2f64.powi(1000);
+        exp -= 1000;
+    }
+    while exp < -1000 {
+        x *= 2f64.powi(-1000);
+        exp += 1000;
+    }
+    x * 2f64.powi(exp as i32)
+}
+
+/// Returns the value of a hex float, or None if it is malformed: it needs at
+/// least one hex digit and a binary exponent with at least one digit. The
+/// value is exact whenever it is representable, which is always the case
+/// for literals printed with `%a`.
+pub(crate) fn hex_float_value(text: &str) -> Option<f64> {
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text),
+    };
+    let digits = unsigned.get(2..)?;
+    let (mantissa, exponent) = digits.split_once(['p', 'P'])?;
+    let exponent_digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+    if exponent_digits.is_empty() || !exponent_digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // An exponent too large for an i64 gives 0 or infinity either way.
+    let exponent = match exponent.parse::<i64>() {
+        Ok(exponent) => exponent,
+        Err(_) if exponent.starts_with('-') => -4000,
+        Err(_) => 4000,
+    };
+    let (int_digits, frac_digits) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int_digits.is_empty() && frac_digits.is_empty() {
+        return None;
+    }
+
+    // Keep up to 60 bits of mantissa; digits past that only affect rounding.
+    let mut value: u64 = 0;
+    let mut exp = exponent;
+    for (c, is_fraction) in
+        int_digits.chars().map(|c| (c, false)).chain(frac_digits.chars().map(|c| (c, true)))
+    {
+        let digit = c.to_digit(16)? as u64;
+        if value < 1 << 56 {
+            value = value * 16 + digit;
+            if is_fraction {
+                exp -= 4;
+            }
+        } else if !is_fraction {
+            exp += 4;
+        }
+    }
+    // Past 2^±4000 every mantissa underflows to 0 or overflows.
+    let magnitude = scale_by_power_of_two(value as f64, exp.clamp(-4000, 4000));
+    Some(if negative { -magnitude } else { magnitude })
+}
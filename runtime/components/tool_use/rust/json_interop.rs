@@ -0,0 +1,480 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversions between the JSON tool call formats used by other model
+//! providers and the ToolCalls proto.
+
+use crate::value_utils::{rewrite_tool_calls, Rewrite};
+use crate::{ffi, to_tool_call_result};
+use protobuf::proto;
+use serde_json::Map;
+use std::convert::Infallible;
+use tool_call_rust_proto::value::KindOneof;
+use tool_call_rust_proto::{
+    Field, ListValue, NullValue, Struct, StructView, ToolCall, ToolCallView, ToolCalls, Value,
+    ValueView,
+};
+
+type JsonValue = serde_json::Value;
+
+pub(crate) fn json_to_value(json: &JsonValue) -> Result<Value, String> {
+    match json {
+        JsonValue::Null => Ok(proto!(Value { null_value: NullValue::default() })),
+        JsonValue::Bool(b) => Ok(proto!(Value { bool_value: *b })),
+        JsonValue::Number(n) => match n.as_f64() {
+            Some(double_val) => Ok(proto!(Value { number_value: double_val })),
+            None => Err(format!("Failed to parse number: {}", n)),
+        },
+        JsonValue::String(s) => Ok(proto!(Value { string_value: s.clone() })),
+        JsonValue::Array(values) => {
+            let mut list_value = ListValue::new();
+            for value in values {
+                list_value.values_mut().push(json_to_value(value)?);
+            }
+            Ok(proto!(Value { list_value: list_value }))
+        }
+        JsonValue::Object(map) => {
+            let s = json_to_struct(map)?;
+            Ok(proto!(Value { struct_value: s }))
+        }
+    }
+}
+
+pub(crate) fn json_to_struct(map: &Map<String, JsonValue>) -> Result<Struct, String> {
+    let mut object = Struct::new();
+    for (key, value) in map {
+        let parsed_value = json_to_value(value)
+            .map_err(|e| format!("Error parsing value for key '{}': {}", key, e))?;
+
+        let mut field = Field::new();
+        field.set_name(key.clone());
+        field.set_value(parsed_value);
+        object.fields_mut().push(field);
+    }
+    Ok(object)
+}
+
+fn struct_to_json(s: StructView) -> JsonValue {
+    JsonValue::Object(
+        s.fields()
+            .iter()
+            .map(|field| (field.name().to_string(), value_to_json(field.value())))
+            .collect(),
+    )
+}
+
+// Mirrors ValueToJson in parser_common.cc. Non-finite numbers become null,
+// as they have no JSON representation, and bytes a base64 string.
+fn value_to_json(value: ValueView) -> JsonValue {
+    match value.kind() {
+        KindOneof::NumberValue(n) => {
+            serde_json::Number::from_f64(n).map_or(JsonValue::Null, JsonValue::Number)
+        }
+        KindOneof::IntValue(n) => JsonValue::from(n),
+        KindOneof::StringValue(s) => JsonValue::String(s.to_string()),
+        KindOneof::BoolValue(b) => JsonValue::Bool(b),
+        KindOneof::BytesValue(bytes) => JsonValue::String(crate::value_utils::base64_encode(bytes)),
+        KindOneof::ReferenceValue(name) => serde_json::json!({"$ref": name.to_string()}),
+        KindOneof::StructValue(s) => struct_to_json(s),
+        KindOneof::ListValue(l) => JsonValue::Array(l.values().iter().map(value_to_json).collect()),
+        KindOneof::CallValue(call) => serde_json::json!({"$call": tool_call_to_json(call)}),
+        _ => JsonValue::Null,
+    }
+}
+
+// Mirrors ToolCallToJson in parser_common.cc.
+fn tool_call_to_json(tool_call: ToolCallView) -> JsonValue {
+    let mut object = Map::new();
+    object.insert("name".to_string(), JsonValue::String(tool_call.name().to_string()));
+    object.insert("arguments".to_string(), struct_to_json(tool_call.arguments()));
+    if !tool_call.spread_refs().is_empty() {
+        let spread_refs: Vec<String> =
+            tool_call.spread_refs().iter().map(|name| name.to_string()).collect();
+        object.insert("spread_refs".to_string(), JsonValue::from(spread_refs));
+    }
+    if !tool_call.comments().is_empty() {
+        let comments: Vec<String> =
+            tool_call.comments().iter().map(|comment| comment.to_string()).collect();
+        object.insert("comments".to_string(), JsonValue::from(comments));
+    }
+    // A non-finite confidence becomes null, as numbers do.
+    if tool_call.has_confidence() {
+        object.insert("confidence".to_string(), JsonValue::from(tool_call.confidence()));
+    }
+    if !tool_call.dispatch_verb().is_empty() {
+        let verb = tool_call.dispatch_verb().to_string();
+        object.insert("dispatch_verb".to_string(), JsonValue::String(verb));
+    }
+    JsonValue::Object(object)
+}
+
+/// Converts `tool_calls` to the JSON the C++ parsers return, e.g.
+/// `[{"name": "f", "arguments": {"x": 1}}]`, adding spread_refs, comments,
+/// confidence and dispatch_verb to a call that has them. A nested call becomes a
+/// `{"$call": {"name": ..., "arguments": ...}}` object, as a reference
+/// becomes `{"$ref": name}`.
+pub(crate) fn tool_calls_to_json(tool_calls: &ToolCalls) -> JsonValue {
+    JsonValue::Array(tool_calls.tool_calls().iter().map(tool_call_to_json).collect())
+}
+
+// Decodes a string holding a JSON object or array, or returns None for any
+// other string.
+fn decode_json_string(s: &str) -> Option<Value> {
+    if !s.trim_start().starts_with(['{', '[']) {
+        return None;
+    }
+    let json = serde_json::from_str::<JsonValue>(s).ok()?;
+    json_to_value(&json).ok()
+}
+
+// Decodes the strings decode_json_string accepts, in the arguments of the
+// calls but not of calls nested in them.
+struct DecodeStrings;
+
+impl Rewrite for DecodeStrings {
+    type Error = Infallible;
+    const INTO_CALLS: bool = false;
+
+    fn before(
+        &mut self,
+        _path: &str,
+        _key: Option<&str>,
+        value: ValueView,
+    ) -> Result<Option<Value>, Infallible> {
+        Ok(match value.kind() {
+            KindOneof::StringValue(s) => decode_json_string(&s.to_string()),
+            _ => None,
+        })
+    }
+}
+
+/// Replaces every string argument that holds a JSON-encoded object or array
+/// with the decoded struct or list. Other strings are left untouched.
+pub(crate) fn decode_string_json(tool_calls: &mut ToolCalls) {
+    rewrite_tool_calls(tool_calls, &mut DecodeStrings).unwrap_or_else(|never| match never {})
+}
+
+// Parses the stringified arguments of an OpenAI tool call. An empty string is
+// treated as a call without arguments.
+fn parse_openai_arguments(arguments: &JsonValue) -> Result<Struct, String> {
+    let parsed;
+    let arguments = match arguments {
+        JsonValue::String(s) if s.trim().is_empty() => return Ok(Struct::new()),
+        JsonValue::String(s) => {
+            parsed = serde_json::from_str::<JsonValue>(s)
+                .map_err(|e| format!("Failed to parse arguments JSON: {}", e))?;
+            &parsed
+        }
+        other => other,
+    };
+    match arguments {
+        JsonValue::Object(map) => json_to_struct(map),
+        _ => Err("Arguments must be a JSON object".to_string()),
+    }
+}
+
+fn parse_openai_tool_call(json: &JsonValue) -> Result<ToolCall, String> {
+    let call = json.as_object().ok_or_else(|| "Tool call must be a JSON object".to_string())?;
+    if let Some(call_type) = call.get("type") {
+        if call_type.as_str() != Some("function") {
+            return Err(format!("Unsupported tool call type: {}", call_type));
+        }
+    }
+    let function = call
+        .get("function")
+        .and_then(JsonValue::as_object)
+        .ok_or_else(|| "Tool call is missing the 'function' object".to_string())?;
+    let name = function
+        .get("name")
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| "Function is missing a string 'name'".to_string())?;
+
+    let mut tool_call = ToolCall::new();
+    tool_call.set_name(name);
+    if let Some(arguments) = function.get("arguments") {
+        tool_call.set_arguments(parse_openai_arguments(arguments)?);
+    }
+    Ok(tool_call)
+}
+
+fn openai_tool_calls(json: &str) -> Result<ToolCalls, String> {
+    let parsed: JsonValue =
+        serde_json::from_str(json).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    let calls =
+        parsed.as_array().ok_or_else(|| "Expected a JSON array of tool calls".to_string())?;
+
+    let mut tool_calls = ToolCalls::new();
+    for (index, call) in calls.iter().enumerate() {
+        let tool_call = parse_openai_tool_call(call)
+            .map_err(|e| format!("Error parsing tool call at index {}: {}", index, e))?;
+        tool_calls.tool_calls_mut().push(tool_call);
+    }
+    Ok(tool_calls)
+}
+
+/// Parses an OpenAI chat completions `tool_calls` array, e.g.
+/// `[{"id": "call_1", "type": "function", "function": {"name": "f",
+/// "arguments": "{\"x\": 1}"}}]`, into the ToolCalls proto.
+pub fn parse_openai_tool_calls(json: &str) -> ffi::ToolCallResult {
+    to_tool_call_result(openai_tool_calls(json))
+}
+
+fn parse_gemini_function_call(json: &JsonValue) -> Result<ToolCall, String> {
+    let function_call = json
+        .get("functionCall")
+        .and_then(JsonValue::as_object)
+        .ok_or_else(|| "Expected an object with a 'functionCall' object".to_string())?;
+    let name = function_call
+        .get("name")
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| "functionCall is missing a string 'name'".to_string())?;
+
+    let mut tool_call = ToolCall::new();
+    tool_call.set_name(name);
+    match function_call.get("args") {
+        Some(JsonValue::Object(args)) => tool_call.set_arguments(json_to_struct(args)?),
+        Some(JsonValue::Null) | None => {}
+        Some(_) => return Err("functionCall 'args' must be a JSON object".to_string()),
+    }
+    Ok(tool_call)
+}
+
+fn gemini_function_calls(json: &str) -> Result<ToolCalls, String> {
+    let parsed: JsonValue =
+        serde_json::from_str(json).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+
+    let mut tool_calls = ToolCalls::new();
+    match &parsed {
+        JsonValue::Array(calls) => {
+            for (index, call) in calls.iter().enumerate() {
+                let tool_call = parse_gemini_function_call(call).map_err(|e| {
+                    format!("Error parsing function call at index {}: {}", index, e)
+                })?;
+                tool_calls.tool_calls_mut().push(tool_call);
+            }
+        }
+        JsonValue::Object(_) => {
+            tool_calls.tool_calls_mut().push(parse_gemini_function_call(&parsed)?)
+        }
+        _ => return Err("Expected a functionCall object or a JSON array of them".to_string()),
+    }
+    Ok(tool_calls)
+}
+
+/// Parses Gemini/Vertex `{"functionCall": {"name": "f", "args": {"x": 1}}}`
+/// objects into the ToolCalls proto. Accepts either a single object or a JSON
+/// array of them.
+pub fn parse_gemini_function_calls(json: &str) -> ffi::ToolCallResult {
+    to_tool_call_result(gemini_function_calls(json))
+}
+
+fn tuple_array_tool_calls(json: &str) -> Result<ToolCalls, String> {
+    let parsed: JsonValue =
+        serde_json::from_str(json).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    let tuples = parsed
+        .as_array()
+        .ok_or_else(|| "Expected a JSON array of [name, arguments] tuples".to_string())?;
+
+    let mut tool_calls = ToolCalls::new();
+    for (index, tuple) in tuples.iter().enumerate() {
+        let at_index =
+            |message: &str| format!("Error parsing tuple at index {}: {}", index, message);
+        let (name, arguments) = match tuple.as_array().map(Vec::as_slice) {
+            Some([name, arguments]) => (name, arguments),
+            Some(tuple) => {
+                return Err(at_index(&format!("expected 2 elements, got {}", tuple.len())))
+            }
+            None => return Err(at_index("tuple must be a JSON array")),
+        };
+        let name = name.as_str().ok_or_else(|| at_index("name must be a string"))?;
+        let arguments =
+            arguments.as_object().ok_or_else(|| at_index("arguments must be a JSON object"))?;
+
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name(name);
+        tool_call.set_arguments(json_to_struct(arguments).map_err(|e| at_index(&e))?);
+        tool_calls.tool_calls_mut().push(tool_call);
+    }
+    Ok(tool_calls)
+}
+
+/// Parses a compact JSON array of `[name, arguments]` tuples, e.g.
+/// `[["search", {"q": "cats"}], ["open", {"path": "x"}]]`, into the ToolCalls
+/// proto.
+pub fn parse_tuple_array(json: &str) -> ffi::ToolCallResult {
+    to_tool_call_result(tuple_array_tool_calls(json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> ToolCalls {
+        let result = parse_openai_tool_calls(json);
+        assert!(result.is_ok, "{}", result.error);
+        ToolCalls::parse(&result.serialized_tool_calls).unwrap()
+    }
+
+    fn argument(tool_calls: &ToolCalls, index: usize, key: &str) -> Value {
+        tool_calls
+            .tool_calls()
+            .get(index)
+            .unwrap()
+            .arguments()
+            .fields()
+            .iter()
+            .find(|field| field.name() == key)
+            .unwrap()
+            .value()
+            .to_owned()
+    }
+
+    #[test]
+    fn converts_nested_calls_to_call_objects() {
+        let options = crate::ParseOptions { nested_calls: true, ..Default::default() };
+        let result = crate::parse_fc_expression_with_options(
+            "call:f{n:call:g{x:[1,$y]}, m:[call:h{}]}",
+            &options,
+        );
+        assert!(result.is_ok, "{}", result.error);
+        let tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+        assert_eq!(
+            tool_calls_to_json(&tool_calls),
+            serde_json::json!([{"name": "f", "arguments": {
+                "n": {"$call": {"name": "g", "arguments": {"x": [1.0, {"$ref": "y"}]}}},
+                "m": [{"$call": {"name": "h", "arguments": {}}}],
+            }}])
+        );
+    }
+
+    #[test]
+    fn keeps_every_field_of_a_call() {
+        let options = crate::ParseOptions {
+            capture_comments: true,
+            call_confidence: true,
+            dispatch_verbs: ["INVOKE"].into_iter().map(String::from).collect(),
+            ..Default::default()
+        };
+        let result = crate::parse_fc_expression_with_options(
+            "// first\ncall:f{**$base,x:1} @0.87, INVOKE call:g{}",
+            &options,
+        );
+        assert!(result.is_ok, "{}", result.error);
+        let tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+        assert_eq!(
+            tool_calls_to_json(&tool_calls),
+            serde_json::json!([
+                {"name": "f", "arguments": {"x": 1.0}, "spread_refs": ["base"],
+                 "comments": ["// first"], "confidence": 0.87},
+                {"name": "g", "arguments": {}, "dispatch_verb": "INVOKE"},
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_two_openai_calls() {
+        let tool_calls = parse(
+            r#"[
+              {"id": "call_1", "type": "function",
+               "function": {"name": "search", "arguments": "{\"q\": \"cats\", \"limit\": 3}"}},
+              {"id": "call_2", "type": "function",
+               "function": {"name": "open", "arguments": "{\"path\": {\"dir\": \"x\"}}"}}
+            ]"#,
+        );
+
+        assert_eq!(tool_calls.tool_calls().len(), 2);
+        assert_eq!(tool_calls.tool_calls().get(0).unwrap().name().to_string(), "search");
+        assert_eq!(argument(&tool_calls, 0, "q").string_value().to_string(), "cats");
+        assert_eq!(argument(&tool_calls, 0, "limit").number_value(), 3.0);
+
+        assert_eq!(tool_calls.tool_calls().get(1).unwrap().name().to_string(), "open");
+        let dir =
+            argument(&tool_calls, 1, "path").struct_value().fields().get(0).unwrap().to_owned();
+        assert_eq!(dir.name().to_string(), "dir");
+        assert_eq!(dir.value().string_value().to_string(), "x");
+    }
+
+    #[test]
+    fn reports_index_of_call_with_bad_arguments() {
+        let result = parse_openai_tool_calls(
+            r#"[
+              {"id": "call_1", "type": "function",
+               "function": {"name": "search", "arguments": "{\"q\": \"cats\"}"}},
+              {"id": "call_2", "type": "function",
+               "function": {"name": "open", "arguments": "{\"path\": "}}
+            ]"#,
+        );
+
+        assert!(!result.is_ok);
+        assert!(
+            result.error.contains("Error parsing tool call at index 1"),
+            "unexpected error: {}",
+            result.error
+        );
+    }
+
+    #[test]
+    fn parses_single_gemini_function_call() {
+        let result = parse_gemini_function_calls(
+            r#"{"functionCall": {"name": "get_weather", "args": {"city": "Paris", "days": 2}}}"#,
+        );
+        assert!(result.is_ok, "{}", result.error);
+        let tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+
+        assert_eq!(tool_calls.tool_calls().len(), 1);
+        assert_eq!(tool_calls.tool_calls().get(0).unwrap().name().to_string(), "get_weather");
+        assert_eq!(argument(&tool_calls, 0, "city").string_value().to_string(), "Paris");
+        assert_eq!(argument(&tool_calls, 0, "days").number_value(), 2.0);
+    }
+
+    #[test]
+    fn parses_list_of_gemini_function_calls() {
+        let result = parse_gemini_function_calls(
+            r#"[
+              {"functionCall": {"name": "search", "args": {"q": "cats"}}},
+              {"functionCall": {"name": "list", "args": {"tags": ["a", "b"]}}}
+            ]"#,
+        );
+        assert!(result.is_ok, "{}", result.error);
+        let tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+
+        assert_eq!(tool_calls.tool_calls().len(), 2);
+        assert_eq!(tool_calls.tool_calls().get(0).unwrap().name().to_string(), "search");
+        assert_eq!(argument(&tool_calls, 0, "q").string_value().to_string(), "cats");
+        assert_eq!(tool_calls.tool_calls().get(1).unwrap().name().to_string(), "list");
+        let tags = argument(&tool_calls, 1, "tags");
+        assert_eq!(tags.list_value().values().len(), 2);
+        assert_eq!(tags.list_value().values().get(1).unwrap().string_value().to_string(), "b");
+    }
+
+    #[test]
+    fn parses_tuple_array() {
+        let result = parse_tuple_array(r#"[["search", {"q": "cats"}], ["open", {"path": "x"}]]"#);
+        assert!(result.is_ok, "{}", result.error);
+        let tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+
+        assert_eq!(tool_calls.tool_calls().len(), 2);
+        assert_eq!(tool_calls.tool_calls().get(0).unwrap().name().to_string(), "search");
+        assert_eq!(argument(&tool_calls, 0, "q").string_value().to_string(), "cats");
+        assert_eq!(tool_calls.tool_calls().get(1).unwrap().name().to_string(), "open");
+        assert_eq!(argument(&tool_calls, 1, "path").string_value().to_string(), "x");
+    }
+
+    #[test]
+    fn reports_index_of_tuple_with_wrong_arity() {
+        let result = parse_tuple_array(r#"[["search", {"q": "cats"}], ["open"]]"#);
+        assert!(!result.is_ok);
+        assert_eq!(result.error, "Error parsing tuple at index 1: expected 2 elements, got 1");
+    }
+}
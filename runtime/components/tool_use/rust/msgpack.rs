@@ -0,0 +1,558 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! MessagePack encoding of ToolCalls, enabled by the `msgpack` feature.
+//!
+//! ToolCalls is encoded as an array of `{"name": str, "arguments": map}`
+//! maps, with spread_refs, comments, confidence and dispatch_verb added when
+//! set, as in the CBOR form. int_value is written as a MessagePack integer
+//! and number_value always as a float 64, so the two kinds survive a round
+//! trip. References are extension type 1 holding the name, a nested call is
+//! extension type 2 holding its encoded ToolCall map, and bytes_value is bin.
+
+use protobuf::proto;
+use tool_call_rust_proto::value::KindOneof;
+use tool_call_rust_proto::{
+    Field, ListValue, NullValue, Struct, StructView, ToolCall, ToolCallView, ToolCalls, Value,
+    ValueView,
+};
+
+const NIL: u8 = 0xc0;
+const FALSE: u8 = 0xc2;
+const TRUE: u8 = 0xc3;
+const BIN8: u8 = 0xc4;
+const BIN16: u8 = 0xc5;
+const BIN32: u8 = 0xc6;
+const EXT8: u8 = 0xc7;
+const EXT16: u8 = 0xc8;
+const EXT32: u8 = 0xc9;
+const FLOAT32: u8 = 0xca;
+const FLOAT64: u8 = 0xcb;
+const UINT8: u8 = 0xcc;
+const UINT16: u8 = 0xcd;
+const UINT32: u8 = 0xce;
+const UINT64: u8 = 0xcf;
+const INT8: u8 = 0xd0;
+const INT16: u8 = 0xd1;
+const INT32: u8 = 0xd2;
+const INT64: u8 = 0xd3;
+const FIXEXT1: u8 = 0xd4;
+const FIXEXT16: u8 = 0xd8;
+const STR8: u8 = 0xd9;
+const STR16: u8 = 0xda;
+const STR32: u8 = 0xdb;
+const ARRAY16: u8 = 0xdc;
+const ARRAY32: u8 = 0xdd;
+const MAP16: u8 = 0xde;
+const MAP32: u8 = 0xdf;
+
+const EXT_REFERENCE: i8 = 1;
+const EXT_TOOL_CALL: i8 = 2;
+
+// Arrays, maps and nested calls deeper than this are rejected rather than
+// decoded recursively, so that hostile input cannot overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+// Writes the header of a str, bin, ext, array or map of `len` elements. `fix`
+// is the prefix of the fix form and the length it stays below, and `formats`
+// the 8, 16 and 32 bit forms; a kind of header without one passes None.
+fn write_len(out: &mut Vec<u8>, len: usize, fix: Option<(u8, usize)>, formats: [Option<u8>; 3]) {
+    match (fix, formats) {
+        (Some((prefix, limit)), _) if len < limit => out.push(prefix | len as u8),
+        (_, [Some(format), _, _]) if len <= u8::MAX as usize => {
+            out.push(format);
+            out.push(len as u8);
+        }
+        (_, [_, Some(format), _]) if len <= u16::MAX as usize => {
+            out.push(format);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        (_, [_, _, Some(format)]) => {
+            out.push(format);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        _ => unreachable!("every length has a 32 bit form"),
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(out, bytes.len(), Some((0xa0, 32)), [Some(STR8), Some(STR16), Some(STR32)]);
+    out.extend_from_slice(bytes);
+}
+
+fn write_array_header(out: &mut Vec<u8>, len: usize) {
+    write_len(out, len, Some((0x90, 16)), [None, Some(ARRAY16), Some(ARRAY32)]);
+}
+
+fn write_map_header(out: &mut Vec<u8>, len: usize) {
+    write_len(out, len, Some((0x80, 16)), [None, Some(MAP16), Some(MAP32)]);
+}
+
+fn write_int(out: &mut Vec<u8>, n: i64) {
+    if (-32..=127).contains(&n) {
+        out.push(n as u8);
+    } else if n >= 0 {
+        if n <= u8::MAX as i64 {
+            out.push(UINT8);
+            out.push(n as u8);
+        } else if n <= u16::MAX as i64 {
+            out.push(UINT16);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        } else if n <= u32::MAX as i64 {
+            out.push(UINT32);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        } else {
+            out.push(UINT64);
+            out.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    } else if n >= i8::MIN as i64 {
+        out.push(INT8);
+        out.push(n as i8 as u8);
+    } else if n >= i16::MIN as i64 {
+        out.push(INT16);
+        out.extend_from_slice(&(n as i16).to_be_bytes());
+    } else if n >= i32::MIN as i64 {
+        out.push(INT32);
+        out.extend_from_slice(&(n as i32).to_be_bytes());
+    } else {
+        out.push(INT64);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn write_float(out: &mut Vec<u8>, n: f64) {
+    out.push(FLOAT64);
+    out.extend_from_slice(&n.to_bits().to_be_bytes());
+}
+
+fn write_strs<'a>(out: &mut Vec<u8>, strs: impl ExactSizeIterator<Item = &'a [u8]>) {
+    write_array_header(out, strs.len());
+    for s in strs {
+        write_str(out, s);
+    }
+}
+
+fn write_ext(out: &mut Vec<u8>, ext_type: i8, data: &[u8]) {
+    match data.len() {
+        len @ (1 | 2 | 4 | 8 | 16) => out.push(FIXEXT1 + len.trailing_zeros() as u8),
+        len => write_len(out, len, None, [Some(EXT8), Some(EXT16), Some(EXT32)]),
+    }
+    out.push(ext_type as u8);
+    out.extend_from_slice(data);
+}
+
+fn write_struct(out: &mut Vec<u8>, s: StructView) {
+    write_map_header(out, s.fields().len());
+    for field in s.fields() {
+        write_str(out, field.name().as_bytes());
+        write_value(out, field.value());
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: ValueView) {
+    match value.kind() {
+        KindOneof::NumberValue(n) => write_float(out, n),
+        KindOneof::IntValue(n) => write_int(out, n),
+        KindOneof::StringValue(s) => write_str(out, s.as_bytes()),
+        KindOneof::BytesValue(bytes) => {
+            write_len(out, bytes.len(), None, [Some(BIN8), Some(BIN16), Some(BIN32)]);
+            out.extend_from_slice(bytes);
+        }
+        KindOneof::ReferenceValue(name) => write_ext(out, EXT_REFERENCE, name.as_bytes()),
+        KindOneof::CallValue(call) => {
+            let mut encoded = Vec::new();
+            write_tool_call(&mut encoded, call);
+            write_ext(out, EXT_TOOL_CALL, &encoded);
+        }
+        KindOneof::BoolValue(b) => out.push(if b { TRUE } else { FALSE }),
+        KindOneof::StructValue(s) => write_struct(out, s),
+        KindOneof::ListValue(l) => {
+            write_array_header(out, l.values().len());
+            for element in l.values() {
+                write_value(out, element);
+            }
+        }
+        _ => out.push(NIL),
+    }
+}
+
+fn write_tool_call(out: &mut Vec<u8>, tool_call: ToolCallView) {
+    // Calls without arguments omit the key so that has_arguments() survives
+    // the round trip.
+    let (spread_refs, comments) = (tool_call.spread_refs(), tool_call.comments());
+    let len = 1
+        + usize::from(tool_call.has_arguments())
+        + usize::from(!spread_refs.is_empty())
+        + usize::from(!comments.is_empty())
+        + usize::from(tool_call.has_confidence())
+        + usize::from(!tool_call.dispatch_verb().is_empty());
+    write_map_header(out, len);
+    write_str(out, b"name");
+    write_str(out, tool_call.name().as_bytes());
+    if tool_call.has_arguments() {
+        write_str(out, b"arguments");
+        write_struct(out, tool_call.arguments());
+    }
+    if !spread_refs.is_empty() {
+        write_str(out, b"spread_refs");
+        write_strs(out, spread_refs.iter().map(|name| name.as_bytes()));
+    }
+    if !comments.is_empty() {
+        write_str(out, b"comments");
+        write_strs(out, comments.iter().map(|comment| comment.as_bytes()));
+    }
+    if tool_call.has_confidence() {
+        write_str(out, b"confidence");
+        write_float(out, tool_call.confidence());
+    }
+    if !tool_call.dispatch_verb().is_empty() {
+        write_str(out, b"dispatch_verb");
+        write_str(out, tool_call.dispatch_verb().as_bytes());
+    }
+}
+
+/// Encodes `tool_calls` as MessagePack.
+pub fn to_msgpack(tool_calls: &ToolCalls) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_array_header(&mut out, tool_calls.tool_calls().len());
+    for tool_call in tool_calls.tool_calls() {
+        write_tool_call(&mut out, tool_call);
+    }
+    out
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+enum Item {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bin(Vec<u8>),
+    Ext(i8, Vec<u8>),
+    Array(usize),
+    Map(usize),
+    Bool(bool),
+    Nil,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < n {
+            return Err(format!("Unexpected end of MessagePack input at offset {}", self.pos));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    // Reads a big-endian unsigned length of `width` bytes.
+    fn len(&mut self, width: usize) -> Result<usize, String> {
+        let bytes = self.take(width)?;
+        Ok(bytes.iter().fold(0, |len, &b| len << 8 | b as usize))
+    }
+
+    fn str(&mut self, len: usize) -> Result<Item, String> {
+        String::from_utf8(self.take(len)?.to_vec())
+            .map(Item::Str)
+            .map_err(|e| format!("Invalid UTF-8 in MessagePack str: {}", e))
+    }
+
+    fn ext(&mut self, len: usize) -> Result<Item, String> {
+        let ext_type = self.take(1)?[0] as i8;
+        Ok(Item::Ext(ext_type, self.take(len)?.to_vec()))
+    }
+
+    fn item(&mut self) -> Result<Item, String> {
+        let format = self.take(1)?[0];
+        match format {
+            0x00..=0x7f => Ok(Item::Int(format as i64)),
+            0x80..=0x8f => Ok(Item::Map((format & 0x0f) as usize)),
+            0x90..=0x9f => Ok(Item::Array((format & 0x0f) as usize)),
+            0xa0..=0xbf => self.str((format & 0x1f) as usize),
+            0xe0..=0xff => Ok(Item::Int(format as i8 as i64)),
+            NIL => Ok(Item::Nil),
+            FALSE => Ok(Item::Bool(false)),
+            TRUE => Ok(Item::Bool(true)),
+            BIN8 | BIN16 | BIN32 => {
+                let len = self.len(1 << (format - BIN8))?;
+                Ok(Item::Bin(self.take(len)?.to_vec()))
+            }
+            EXT8 | EXT16 | EXT32 => {
+                let len = self.len(1 << (format - EXT8))?;
+                self.ext(len)
+            }
+            FIXEXT1..=FIXEXT16 => self.ext(1 << (format - FIXEXT1)),
+            FLOAT32 => Ok(Item::Float(f32::from_be_bytes(self.take_array()?) as f64)),
+            FLOAT64 => Ok(Item::Float(f64::from_be_bytes(self.take_array()?))),
+            UINT8 => Ok(Item::Int(self.take(1)?[0] as i64)),
+            UINT16 => Ok(Item::Int(u16::from_be_bytes(self.take_array()?) as i64)),
+            UINT32 => Ok(Item::Int(u32::from_be_bytes(self.take_array()?) as i64)),
+            UINT64 => {
+                let n = u64::from_be_bytes(self.take_array()?);
+                i64::try_from(n)
+                    .map(Item::Int)
+                    .map_err(|_| format!("MessagePack integer {} does not fit in int_value", n))
+            }
+            INT8 => Ok(Item::Int(self.take(1)?[0] as i8 as i64)),
+            INT16 => Ok(Item::Int(i16::from_be_bytes(self.take_array()?) as i64)),
+            INT32 => Ok(Item::Int(i32::from_be_bytes(self.take_array()?) as i64)),
+            INT64 => Ok(Item::Int(i64::from_be_bytes(self.take_array()?))),
+            STR8 | STR16 | STR32 => {
+                let len = self.len(1 << (format - STR8))?;
+                self.str(len)
+            }
+            ARRAY16 | ARRAY32 => Ok(Item::Array(self.len(2 << (format - ARRAY16))?)),
+            MAP16 | MAP32 => Ok(Item::Map(self.len(2 << (format - MAP16))?)),
+            _ => Err(format!(
+                "Unsupported MessagePack format 0x{:02x} at offset {}",
+                format,
+                self.pos - 1
+            )),
+        }
+    }
+
+    fn text(&mut self) -> Result<String, String> {
+        match self.item()? {
+            Item::Str(s) => Ok(s),
+            _ => Err(format!("Expected MessagePack str before offset {}", self.pos)),
+        }
+    }
+
+    fn texts(&mut self) -> Result<Vec<String>, String> {
+        let Item::Array(len) = self.item()? else {
+            return Err(format!("Expected a MessagePack array of str before offset {}", self.pos));
+        };
+        (0..len).map(|_| self.text()).collect()
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        match self.item()? {
+            Item::Float(n) => Ok(n),
+            Item::Int(n) => Ok(n as f64),
+            _ => Err(format!("Expected a MessagePack number before offset {}", self.pos)),
+        }
+    }
+
+    // Counts one more level of nesting, failing past MAX_DEPTH.
+    fn enter(&mut self) -> Result<(), String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!(
+                "MessagePack input nests deeper than {} levels at offset {}",
+                MAX_DEPTH, self.pos
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn structure(&mut self, len: usize) -> Result<Struct, String> {
+        self.enter()?;
+        let mut object = Struct::new();
+        for _ in 0..len {
+            let key = self.text()?;
+            let value = self
+                .value()
+                .map_err(|e| format!("Error parsing value for key '{}': {}", key, e))?;
+            let mut field = Field::new();
+            field.set_name(key);
+            field.set_value(value);
+            object.fields_mut().push(field);
+        }
+        self.depth -= 1;
+        Ok(object)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.item()? {
+            Item::Int(n) => Ok(proto!(Value { int_value: n })),
+            Item::Float(n) => Ok(proto!(Value { number_value: n })),
+            Item::Str(s) => Ok(proto!(Value { string_value: s })),
+            Item::Bin(bytes) => Ok(proto!(Value { bytes_value: bytes })),
+            Item::Bool(b) => Ok(proto!(Value { bool_value: b })),
+            Item::Nil => Ok(proto!(Value { null_value: NullValue::default() })),
+            Item::Array(len) => {
+                self.enter()?;
+                let mut list_value = ListValue::new();
+                for _ in 0..len {
+                    list_value.values_mut().push(self.value()?);
+                }
+                self.depth -= 1;
+                Ok(proto!(Value { list_value: list_value }))
+            }
+            Item::Map(len) => {
+                let s = self.structure(len)?;
+                Ok(proto!(Value { struct_value: s }))
+            }
+            Item::Ext(EXT_REFERENCE, name) => String::from_utf8(name)
+                .map(|name| proto!(Value { reference_value: name }))
+                .map_err(|e| format!("Invalid UTF-8 in MessagePack reference: {}", e)),
+            Item::Ext(EXT_TOOL_CALL, encoded) => {
+                self.enter()?;
+                // The call is decoded from its own bytes but counts against
+                // the same depth.
+                let mut decoder = Decoder { bytes: &encoded, pos: 0, depth: self.depth };
+                let tool_call = decoder.tool_call()?;
+                self.depth -= 1;
+                if decoder.pos != encoded.len() {
+                    return Err("Trailing bytes in MessagePack nested call".to_string());
+                }
+                Ok(proto!(Value { call_value: tool_call }))
+            }
+            Item::Ext(ext_type, _) => {
+                Err(format!("Unsupported MessagePack extension type {}", ext_type))
+            }
+        }
+    }
+
+    fn tool_call(&mut self) -> Result<ToolCall, String> {
+        let Item::Map(len) = self.item()? else {
+            return Err("Tool call must be a MessagePack map".to_string());
+        };
+        let mut tool_call = ToolCall::new();
+        for _ in 0..len {
+            match self.text()?.as_str() {
+                "name" => tool_call.set_name(self.text()?),
+                "arguments" => {
+                    let Item::Map(len) = self.item()? else {
+                        return Err("Tool call arguments must be a MessagePack map".to_string());
+                    };
+                    tool_call.set_arguments(self.structure(len)?);
+                }
+                "spread_refs" => tool_call.set_spread_refs(self.texts()?.into_iter()),
+                "comments" => tool_call.set_comments(self.texts()?.into_iter()),
+                "confidence" => tool_call.set_confidence(self.number()?),
+                "dispatch_verb" => tool_call.set_dispatch_verb(self.text()?),
+                other => return Err(format!("Unknown tool call key '{}'", other)),
+            }
+        }
+        Ok(tool_call)
+    }
+}
+
+/// Decodes ToolCalls previously encoded with [`to_msgpack`]. Integers of
+/// any width become int_value and floats of either width number_value.
+pub fn from_msgpack(bytes: &[u8]) -> Result<ToolCalls, String> {
+    let mut decoder = Decoder { bytes, pos: 0, depth: 0 };
+    let Item::Array(len) = decoder.item()? else {
+        return Err("Expected a MessagePack array of tool calls".to_string());
+    };
+    let mut tool_calls = ToolCalls::new();
+    for index in 0..len {
+        let tool_call = decoder
+            .tool_call()
+            .map_err(|e| format!("Error parsing tool call at index {}: {}", index, e))?;
+        tool_calls.tool_calls_mut().push(tool_call);
+    }
+    if decoder.pos != bytes.len() {
+        return Err(format!(
+            "Trailing bytes after MessagePack tool calls at offset {}",
+            decoder.pos
+        ));
+    }
+    Ok(tool_calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fc_expression_with_options, ParseOptions};
+    use protobuf::prelude::*;
+    use tool_call_rust_proto::value::KindCase;
+
+    #[test]
+    fn round_trips_keeping_integers_and_floats_apart() {
+        let options =
+            ParseOptions { integer_values: true, nested_calls: true, ..Default::default() };
+        let long = "x".repeat(300);
+        let result = parse_fc_expression_with_options(
+            &format!(
+                "call:f{{a:1,b:1.0,c:-40,d:70000,e:-9000000000,s:<escape>{}<escape>,l:[true,null,{{g:[]}}],r:$x,n:call:g{{x:[1]}}}}",
+                long
+            ),
+            &options,
+        );
+        assert!(result.is_ok, "{}", result.error);
+        let parsed = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+        let mut first = parsed.tool_calls().get(0).unwrap().to_owned();
+        first.set_spread_refs(["base", "extra"].into_iter());
+        first.set_comments(["// first"].into_iter());
+        first.set_confidence(0.87);
+        first.set_dispatch_verb("INVOKE");
+        let mut second = ToolCall::new();
+        second.set_name("g");
+        second.set_confidence(1.0);
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(first);
+        tool_calls.tool_calls_mut().push(second);
+
+        let encoded = to_msgpack(&tool_calls);
+        let decoded = from_msgpack(&encoded).unwrap();
+        assert_eq!(decoded.serialize().unwrap(), tool_calls.serialize().unwrap());
+        let fields = decoded.tool_calls().get(0).unwrap().arguments().fields();
+        assert_eq!(fields.get(0).unwrap().value().kind_case(), KindCase::IntValue);
+        assert_eq!(fields.get(1).unwrap().value().kind_case(), KindCase::NumberValue);
+
+        assert!(from_msgpack(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_input_nested_too_deeply() {
+        let mut encoded = vec![0x91, 0x82, 0xa4];
+        encoded.extend_from_slice(b"name");
+        encoded.extend_from_slice(&[0xa1, b'f', 0xa9]);
+        encoded.extend_from_slice(b"arguments");
+        encoded.extend_from_slice(&[0x81, 0xa1, b'a']);
+        encoded.extend(std::iter::repeat_n(0x91, 10_000));
+        encoded.push(NIL);
+        let error = from_msgpack(&encoded).unwrap_err();
+        assert!(error.contains("nests deeper than 128 levels"), "unexpected error: {}", error);
+
+        // Calls nested inside calls count against the same limit.
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name("f");
+        for _ in 0..200 {
+            let mut outer = ToolCall::new();
+            outer.set_name("f");
+            let mut field = Field::new();
+            field.set_name("n");
+            field.set_value(proto!(Value { call_value: tool_call }));
+            outer.arguments_mut().fields_mut().push(field);
+            tool_call = outer;
+        }
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(tool_call);
+        let error = from_msgpack(&to_msgpack(&tool_calls)).unwrap_err();
+        assert!(error.contains("nests deeper than 128 levels"), "unexpected error: {}", error);
+    }
+
+    #[test]
+    fn uses_the_smallest_integer_formats() {
+        let mut out = Vec::new();
+        for n in [1, -1, -33, 200, -200, 1 << 40] {
+            write_int(&mut out, n);
+        }
+        assert_eq!(
+            out,
+            [0x01, 0xff, 0xd0, 0xdf, 0xcc, 0xc8, 0xd1, 0xff, 0x38, 0xcf, 0, 0, 1, 0, 0, 0, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,752 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Text-level scanning used to locate FC expressions inside model output.
+
+use crate::ParseOptions;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+pub(crate) const ESCAPE: &str = "<escape>";
+
+pub(crate) fn is_id_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+// Returns the offset just past the string starting at `start`, or None if it
+// is not terminated.
+fn skip_escaped_string(text: &str, start: usize) -> Option<usize> {
+    let body = start + ESCAPE.len();
+    text[body..].find(ESCAPE).map(|i| body + i + ESCAPE.len())
+}
+
+// Returns the offset just past the QUOTED_STRING starting at `start`, or
+// None if it is not terminated. As in the lexer, a backslash escapes the next
+// character inside `"..."` but not inside `'...'`.
+fn skip_quoted_string(text: &str, start: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b if b == quote => return Some(i + 1),
+            b'\\' if quote == b'"' => i += 2,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+// Returns the offset just past the `//` or `/* */` comment starting at
+// `start`, or None if a block comment is not terminated.
+fn skip_comment(text: &str, start: usize) -> Option<usize> {
+    let rest = &text[start..];
+    if rest.starts_with("//") {
+        return Some(start + rest.find(['\n', '\r']).unwrap_or(rest.len()));
+    }
+    rest[2..].find("*/").map(|len| start + len + 4)
+}
+
+// Returns the end of the balanced `{...}` or `[...]` starting at `open`,
+// ignoring brackets inside escaped and quoted strings and, with `comments`,
+// inside comments.
+fn balanced_end(text: &str, open: usize, comments: bool) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut stack = Vec::new();
+    let mut i = open;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if rest.starts_with(ESCAPE.as_bytes()) {
+            i = skip_escaped_string(text, i)?;
+            continue;
+        }
+        if rest.starts_with(b"'") || rest.starts_with(b"\"") {
+            i = skip_quoted_string(text, i)?;
+            continue;
+        }
+        if comments && (rest.starts_with(b"//") || rest.starts_with(b"/*")) {
+            i = skip_comment(text, i)?;
+            continue;
+        }
+        match bytes[i] {
+            b'{' => stack.push(b'}'),
+            b'[' => stack.push(b']'),
+            b'}' | b']' => {
+                if stack.pop() != Some(bytes[i]) {
+                    return None;
+                }
+                if stack.is_empty() {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// Returns the length of the identifier at the start of `text`. A character of
+// `extra` counts as part of it where an identifier character follows, as in
+// mask_identifier_chars.
+fn identifier_len(text: &str, extra: &HashSet<char>) -> usize {
+    if !text.bytes().next().is_some_and(|b| is_id_char(b) && !b.is_ascii_digit()) {
+        return 0;
+    }
+    let mut len = 0;
+    while let Some(c) = text[len..].chars().next() {
+        let next = len + c.len_utf8();
+        let continues = text[next..].bytes().next().is_some_and(is_id_char);
+        if !(c.is_ascii() && is_id_char(c as u8) || extra.contains(&c) && continues) {
+            break;
+        }
+        len = next;
+    }
+    len
+}
+
+// Matches `call : ID {` or, for positional arguments, `call : ID [` at
+// `start` and returns the offset of the bracket. ID may hold the characters
+// of `extra`.
+fn match_call_header(text: &str, start: usize, extra: &HashSet<char>) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut i = skip_whitespace(bytes, start + "call".len());
+    if bytes.get(i) != Some(&b':') {
+        return None;
+    }
+    i = skip_whitespace(bytes, i + 1);
+    let name_len = identifier_len(&text[i..], extra);
+    if name_len == 0 {
+        return None;
+    }
+    i = skip_whitespace(bytes, i + name_len);
+    matches!(bytes.get(i), Some(b'{' | b'[')).then_some(i)
+}
+
+// Matches `call : ID {...}` at `start` and returns the end of the call.
+fn match_call(text: &str, start: usize, options: &ParseOptions) -> Option<usize> {
+    let open = match_call_header(text, start, &options.extra_id_chars)?;
+    balanced_end(text, open, options.capture_comments)
+}
+
+/// Returns the offset of the first `call:name{` at or after `from`, outside
+/// escaped strings, whether or not its braces balance. Used to resynchronize
+/// after a malformed call, so an unterminated string is skipped over rather
+/// than taken to run to the end.
+pub(crate) fn next_call_start(text: &str, from: usize, options: &ParseOptions) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut i = from;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(ESCAPE.as_bytes()) {
+            if let Some(end) = skip_escaped_string(text, i) {
+                i = end;
+                continue;
+            }
+        }
+        let at_word_start = i == 0 || !is_id_char(bytes[i - 1]);
+        if at_word_start
+            && bytes[i..].starts_with(b"call")
+            && match_call_header(text, i, &options.extra_id_chars).is_some()
+        {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Returns the end of the call starting at `start`, as found by
+/// [`next_call_start`], or None if its braces do not balance.
+pub(crate) fn call_end(text: &str, start: usize, options: &ParseOptions) -> Option<usize> {
+    match_call(text, start, options)
+}
+
+/// Finds the first complete `call:name{...}` in `text`, skipping over prose
+/// and over escaped strings that appear in it. Quoted strings and, with
+/// capture_comments, comments inside the call do not end it early, and names
+/// may hold the characters of extra_id_chars.
+pub(crate) fn find_call_span(text: &str, options: &ParseOptions) -> Option<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(ESCAPE.as_bytes()) {
+            match skip_escaped_string(text, i) {
+                Some(end) => {
+                    i = end;
+                    continue;
+                }
+                None => return None,
+            }
+        }
+        let at_word_start = i == 0 || !is_id_char(bytes[i - 1]);
+        if at_word_start && bytes[i..].starts_with(b"call") {
+            if let Some(end) = match_call(text, i, options) {
+                return Some((i, end));
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Finds every complete call in `text` as [`find_call_span`] does, each
+/// taken with the dispatch verb before it and, with call_confidence, the
+/// score after it.
+pub(crate) fn find_call_spans(text: &str, options: &ParseOptions) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut from = 0;
+    while let Some((start, end)) = find_call_span(&text[from..], options) {
+        let (start, end) = (from + start, from + end);
+        let start = dispatch_verb_before(text, start, &options.dispatch_verbs)
+            .filter(|verb| *verb >= from)
+            .unwrap_or(start);
+        let end = if options.call_confidence { score_end(text, end) } else { end };
+        spans.push((start, end));
+        from = end;
+    }
+    spans
+}
+
+/// Returns `text` from the start of the first of `spans` to the end of the
+/// last, with the text between them replaced by spaces of the same length,
+/// newlines aside, so that offsets into it stay valid.
+pub(crate) fn blank_between(text: &str, spans: &[(usize, usize)]) -> String {
+    let (Some(&(first, _)), Some(&(_, last))) = (spans.first(), spans.last()) else {
+        return String::new();
+    };
+    let mut kept = String::with_capacity(last - first);
+    let mut pos = first;
+    for &(start, end) in spans {
+        for c in text[pos..start].chars() {
+            if c == '\n' || c == '\r' {
+                kept.push(c);
+            } else {
+                kept.extend(std::iter::repeat_n(' ', c.len_utf8()));
+            }
+        }
+        kept.push_str(&text[start..end]);
+        pos = end;
+    }
+    kept
+}
+
+// What the text at a word start shows of a `call : ID {` header. Only the
+// stream adapter reads the offset of the `{`.
+#[cfg_attr(not(feature = "async"), allow(dead_code))]
+pub(crate) enum Header {
+    // A header, with the offset of its `{`.
+    Open(usize),
+    // The start of a header the next chunk may finish.
+    Partial,
+    NotACall,
+}
+
+pub(crate) fn match_header(bytes: &[u8], start: usize) -> Header {
+    let rest = &bytes[start..];
+    if rest.len() < "call".len() {
+        return if b"call".starts_with(rest) { Header::Partial } else { Header::NotACall };
+    }
+    if !rest.starts_with(b"call") {
+        return Header::NotACall;
+    }
+    let mut i = skip_whitespace(bytes, start + "call".len());
+    match bytes.get(i) {
+        None => return Header::Partial,
+        Some(b':') => i = skip_whitespace(bytes, i + 1),
+        Some(_) => return Header::NotACall,
+    }
+    let name_start = i;
+    while i < bytes.len() && is_id_char(bytes[i]) {
+        i += 1;
+    }
+    i = skip_whitespace(bytes, i);
+    match bytes.get(i) {
+        None => Header::Partial,
+        Some(b'{') if i > name_start && !bytes[name_start].is_ascii_digit() => Header::Open(i),
+        Some(_) => Header::NotACall,
+    }
+}
+
+/// Replaces each backslash-newline outside escaped strings with spaces of the
+/// same length, so that the lexer skips it as whitespace. `\n`, `\r\n` and a
+/// lone `\r` all end a line. A backslash at the very end of the text is an
+/// error.
+pub(crate) fn join_line_continuations(text: &str) -> Result<String, String> {
+    let bytes = text.as_bytes();
+    let mut joined = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(ESCAPE.as_bytes()) {
+            let end = skip_escaped_string(text, i).unwrap_or(text.len());
+            joined.push_str(&text[i..end]);
+            i = end;
+            continue;
+        }
+        if bytes[i] == b'\\' {
+            let rest = &text[i + 1..];
+            if rest.is_empty() {
+                return Err("Line continuation at end of input".to_string());
+            }
+            let newline = if rest.starts_with("\r\n") {
+                2
+            } else {
+                usize::from(rest.starts_with(['\n', '\r']))
+            };
+            if newline > 0 {
+                joined.extend(std::iter::repeat_n(' ', 1 + newline));
+                i += 1 + newline;
+                continue;
+            }
+        }
+        let len = text[i..].chars().next().map_or(1, char::len_utf8);
+        joined.push_str(&text[i..i + len]);
+        i += len;
+    }
+    Ok(joined)
+}
+
+/// Replaces each `//` line comment and `/* */` block comment outside escaped
+/// strings with spaces, keeping its newlines, so that offsets into the text
+/// stay valid. Returns the blanked text with each comment, delimiters
+/// included, and its offset. An unterminated block comment is an error.
+pub(crate) fn blank_comments(text: &str) -> Result<(String, Vec<(usize, String)>), String> {
+    let mut blanked = String::with_capacity(text.len());
+    let mut comments = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let len = if rest.starts_with(ESCAPE) {
+            let end = skip_escaped_string(text, i).unwrap_or(text.len());
+            blanked.push_str(&text[i..end]);
+            i = end;
+            continue;
+        } else if rest.starts_with("//") {
+            rest.find(['\n', '\r']).unwrap_or(rest.len())
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            let len = body
+                .find("*/")
+                .ok_or_else(|| format!("Unterminated block comment at byte {}", i))?;
+            len + 4
+        } else {
+            let len = rest.chars().next().map_or(1, char::len_utf8);
+            blanked.push_str(&rest[..len]);
+            i += len;
+            continue;
+        };
+        let comment = &rest[..len];
+        for c in comment.chars() {
+            if c == '\n' || c == '\r' {
+                blanked.push(c);
+            } else {
+                blanked.extend(std::iter::repeat_n(' ', c.len_utf8()));
+            }
+        }
+        comments.push((i, comment.to_string()));
+        i += len;
+    }
+    Ok((blanked, comments))
+}
+
+/// Replaces each word in `options.dispatch_verbs` that comes right before a
+/// top-level `call:name{`, separated from it by whitespace, with spaces of
+/// the same length. Returns the blanked text with each verb and the offset of
+/// the call it belongs to.
+pub(crate) fn blank_dispatch_verbs(
+    text: &str,
+    options: &ParseOptions,
+) -> (String, Vec<(usize, String)>) {
+    let verbs = &options.dispatch_verbs;
+    let bytes = text.as_bytes();
+    let mut blanked = text.to_string();
+    let mut found = Vec::new();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(ESCAPE.as_bytes()) {
+            i = skip_escaped_string(text, i).unwrap_or(bytes.len());
+            continue;
+        }
+        match bytes[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            b if depth == 0 && is_id_char(b) && (i == 0 || !is_id_char(bytes[i - 1])) => {
+                let end = i + bytes[i..].iter().take_while(|b| is_id_char(**b)).count();
+                let call = skip_whitespace(bytes, end);
+                if call > end
+                    && verbs.contains(&text[i..end])
+                    && bytes[call..].starts_with(b"call")
+                    && match_call_header(text, call, &options.extra_id_chars).is_some()
+                {
+                    blanked.replace_range(i..end, &" ".repeat(end - i));
+                    found.push((call, text[i..end].to_string()));
+                }
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    (blanked, found)
+}
+
+/// Returns the end of the `@0.87` score written after the call ending at
+/// `end`, or `end` if none follows it.
+pub(crate) fn score_end(text: &str, end: usize) -> usize {
+    let bytes = text.as_bytes();
+    let at = skip_whitespace(bytes, end);
+    if bytes.get(at) != Some(&b'@') {
+        return end;
+    }
+    let digits =
+        |i: usize| i + bytes[i..].iter().take_while(|b| b.is_ascii_digit() || **b == b'_').count();
+    let is_digit = |i: usize| bytes.get(i).is_some_and(u8::is_ascii_digit);
+    let start = skip_whitespace(bytes, at + 1);
+    let mut i = start + usize::from(bytes.get(start) == Some(&b'-'));
+    let int_start = i;
+    if is_digit(i) {
+        i = digits(i);
+    }
+    if bytes.get(i) == Some(&b'.') && is_digit(i + 1) {
+        i = digits(i + 1);
+    }
+    if i == int_start {
+        return end;
+    }
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(i + 1), Some(b'+' | b'-')));
+        if is_digit(i + 1 + sign) {
+            i = digits(i + 1 + sign);
+        }
+    }
+    i
+}
+
+/// Returns the offset of the word in `verbs` written right before the call
+/// at `start`, if there is one.
+pub(crate) fn dispatch_verb_before(
+    text: &str,
+    start: usize,
+    verbs: &HashSet<String>,
+) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let end = bytes[..start].iter().rposition(|b| !b.is_ascii_whitespace())? + 1;
+    let word = bytes[..end].iter().rev().take_while(|b| is_id_char(**b)).count();
+    (end < start && word > 0 && verbs.contains(&text[end - word..end])).then_some(end - word)
+}
+
+/// Returns the offset of the first `<escape>` outside every `{...}` and
+/// `[...]`, where no string can start.
+pub(crate) fn find_top_level_escape(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(ESCAPE.as_bytes()) {
+            if depth == 0 {
+                return Some(i);
+            }
+            // An unterminated string inside a call is unbalanced, not stray.
+            i = skip_escaped_string(text, i)?;
+            continue;
+        }
+        match bytes[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Returns the offset of the first `<escape>` that has no closing sentinel.
+pub(crate) fn find_unbalanced_escape(text: &str) -> Option<usize> {
+    let mut i = 0;
+    while let Some(offset) = text[i..].find(ESCAPE) {
+        let start = i + offset;
+        match skip_escaped_string(text, start) {
+            Some(end) => i = end,
+            None => return Some(start),
+        }
+    }
+    None
+}
+
+/// Returns the text of a QUOTED_STRING token with shell semantics: a
+/// `'...'` string is taken literally, backslashes included, and a `"..."`
+/// string decodes `\t`, `\n`, `\r`, `\\`, `\"`, `\$` and `` \` ``, keeping the
+/// backslash of any other escape. Nothing is expanded.
+pub(crate) fn unquote_shell(token: &str) -> Cow<'_, str> {
+    let body = &token[1..token.len() - 1];
+    if token.starts_with('\'') || !body.contains('\\') {
+        return Cow::Borrowed(body);
+    }
+    let mut unquoted = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unquoted.push('\t'),
+            Some('n') => unquoted.push('\n'),
+            Some('r') => unquoted.push('\r'),
+            Some(c @ ('\\' | '"' | '$' | '`')) => unquoted.push(c),
+            Some(c) => {
+                unquoted.push('\\');
+                unquoted.push(c);
+            }
+            None => unquoted.push('\\'),
+        }
+    }
+    Cow::Owned(unquoted)
+}
+
+/// Returns the identifier `text` consists of, surrounding whitespace aside,
+/// unless it is a keyword.
+pub(crate) fn lone_identifier(text: &str) -> Option<&str> {
+    let word = text.trim();
+    let mut bytes = word.bytes();
+    let is_id =
+        bytes.next().is_some_and(|b| b.is_ascii_alphabetic() || b == b'_') && bytes.all(is_id_char);
+    (is_id && !matches!(word, "call" | "true" | "false" | "null")).then_some(word)
+}
+
+/// Replaces each character of `extra` that sits inside an identifier with
+/// underscores of the same byte length, so that the lexer reads the whole
+/// identifier as one ID and every offset still points at the original text.
+/// An identifier starts with a letter or underscore, so numbers such as
+/// `1e-5` and `$` references are left alone, and an extra character only
+/// counts as part of one when an identifier character follows it. Escaped
+/// and quoted strings are copied as they are.
+pub(crate) fn mask_identifier_chars(text: &str, extra: &HashSet<char>) -> Result<String, String> {
+    if let Some(c) = extra.iter().find(|&&c| c.is_whitespace() || "{}[]:,$<>".contains(c)) {
+        return Err(format!("{:?} cannot be an identifier character", c));
+    }
+    let mut masked = String::with_capacity(text.len());
+    // Some(true) inside an identifier, Some(false) inside another word.
+    let mut word = None;
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        if rest.starts_with(ESCAPE) {
+            let end = skip_escaped_string(text, i).unwrap_or(text.len());
+            masked.push_str(&text[i..end]);
+            i = end;
+            word = None;
+            continue;
+        }
+        if rest.starts_with(['\'', '"']) {
+            if let Some(end) = skip_quoted_string(text, i) {
+                masked.push_str(&text[i..end]);
+                i = end;
+                word = None;
+                continue;
+            }
+        }
+        let c = rest.chars().next().unwrap();
+        let len = c.len_utf8();
+        let continues = rest[len..].bytes().next().is_some_and(is_id_char);
+        if word == Some(true) && extra.contains(&c) && continues {
+            masked.extend(std::iter::repeat_n('_', len));
+        } else {
+            word = match c {
+                '$' => Some(false),
+                _ if !c.is_ascii() || !is_id_char(c as u8) => None,
+                _ => word.or(Some(!c.is_ascii_digit())),
+            };
+            masked.push(c);
+        }
+        i += len;
+    }
+    Ok(masked)
+}
+
+// Returns the name of a `call:name` line that opens a block of arguments.
+fn block_header(line: &str) -> Option<&str> {
+    let name = line.trim().strip_prefix("call:")?;
+    (!name.is_empty() && name.bytes().all(is_id_char)).then_some(name)
+}
+
+// Whether `line` opens or closes an escaped string, leaving the state of the
+// next line the opposite of its own.
+fn toggles_escape(line: &str) -> bool {
+    line.matches(ESCAPE).count() % 2 == 1
+}
+
+/// Rewrites each `call:name` line followed by indented `key: value` lines
+/// into `call:name{key:value,...}`, leaving every other line as it is. Values
+/// must be scalars, one per line, and every line of a block must have the
+/// same indentation. Lines inside a multi-line escaped string are part of
+/// the string and never start a block.
+pub(crate) fn block_args_to_object(text: &str) -> Result<String, String> {
+    let lines: Vec<&str> = text.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+    let mut rewritten = Vec::with_capacity(lines.len());
+    // Whether an escaped string is open at the start of lines[i].
+    let mut in_string = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(name) = block_header(lines[i]).filter(|_| !in_string) else {
+            in_string ^= toggles_escape(lines[i]);
+            rewritten.push(lines[i].to_string());
+            i += 1;
+            continue;
+        };
+        let mut args = Vec::new();
+        let mut block_indent: Option<&str> = None;
+        i += 1;
+        while i < lines.len() && lines[i].starts_with([' ', '\t']) && !lines[i].trim().is_empty() {
+            let line = lines[i];
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let expected = *block_indent.get_or_insert(indent);
+            if indent.contains(' ') && indent.contains('\t') || indent[..1] != expected[..1] {
+                return Err(format!("Line {} mixes tabs and spaces in its indentation", i + 1));
+            }
+            if indent != expected {
+                return Err(format!("Line {} is indented differently from the line above", i + 1));
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("Line {} is not a key: value pair", i + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            if key.is_empty() || !key.bytes().all(is_id_char) || value.is_empty() {
+                return Err(format!("Line {} is not a key: value pair", i + 1));
+            }
+            if value.starts_with(['{', '[']) {
+                return Err(format!("Block argument {} on line {} is not a scalar", key, i + 1));
+            }
+            args.push(format!("{}:{}", key, value));
+            in_string ^= toggles_escape(line);
+            i += 1;
+        }
+        rewritten.push(format!("call:{}{{{}}}", name, args.join(",")));
+    }
+    Ok(rewritten.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_extra_characters_inside_identifiers_only() {
+        let extra = HashSet::from(['-', '/']);
+        let text = "call:fs/read-file{a-b:1e-5,c:-1,d:$x-y,e:<escape>p/q-r<escape>,f:g- h}";
+        let masked = mask_identifier_chars(text, &extra).unwrap();
+        assert_eq!(
+            masked,
+            "call:fs_read_file{a_b:1e-5,c:-1,d:$x-y,e:<escape>p/q-r<escape>,f:g- h}"
+        );
+        assert!(mask_identifier_chars(text, &HashSet::from([':'])).is_err());
+
+        let text = r#"call:fs/read-file{a-b:'p/q-r',c-d:"x-y \"u-v\" w-z"}"#;
+        let masked = mask_identifier_chars(text, &extra).unwrap();
+        assert_eq!(masked, r#"call:fs_read_file{a_b:'p/q-r',c_d:"x-y \"u-v\" w-z"}"#);
+    }
+
+    fn found(text: &str) -> Option<&str> {
+        find_call_span(text, &ParseOptions::default()).map(|(start, end)| &text[start..end])
+    }
+
+    #[test]
+    fn finds_call_after_prose() {
+        let text = "I'll search now: call:search{q:<escape>cats<escape>} and then stop.";
+        assert_eq!(found(text), Some("call:search{q:<escape>cats<escape>}"));
+    }
+
+    #[test]
+    fn stray_brackets_in_prose_do_not_derail_detection() {
+        let text =
+            "Options {a, b] (see below) recall: x {y} call:f{a:[1,{b:<escape>}<escape>}]} done";
+        assert_eq!(found(text), Some("call:f{a:[1,{b:<escape>}<escape>}]}"));
+    }
+
+    #[test]
+    fn non_ascii_text_does_not_split_characters() {
+        let text = "Voilà: call:f{x:'é',y:<escape>ü<escape>} ¿fin?";
+        assert_eq!(found(text), Some("call:f{x:'é',y:<escape>ü<escape>}"));
+        assert_eq!(found("Voilà: rien"), None);
+        assert_eq!(join_line_continuations("é\\\ncall:f{x:'é'}").unwrap(), "é  call:f{x:'é'}");
+    }
+
+    #[test]
+    fn call_ends_outside_quotes_and_comments() {
+        assert_eq!(found("Sure: call:f{a:'}'} done"), Some("call:f{a:'}'}"));
+        assert_eq!(found(r#"call:f{a:"\"}",b:1}"#), Some(r#"call:f{a:"\"}",b:1}"#));
+        assert_eq!(found("call:f{a:'}"), None);
+
+        let text = "call:f{/* } */ a:1, // ]\n b:2} done";
+        let options = ParseOptions { capture_comments: true, ..Default::default() };
+        let span = find_call_span(text, &options).map(|(start, end)| &text[start..end]);
+        assert_eq!(span, Some("call:f{/* } */ a:1, // ]\n b:2}"));
+        let text = "call:f{/* } */ a:1} done";
+        let span = find_call_span(text, &options).map(|(start, end)| &text[start..end]);
+        assert_eq!(span, Some("call:f{/* } */ a:1}"));
+        assert_eq!(found(text), Some("call:f{/* }"));
+    }
+
+    #[test]
+    fn finds_positional_calls_and_extra_name_characters() {
+        assert_eq!(
+            found("Sure: call:f[<escape>a<escape>,1] ok"),
+            Some("call:f[<escape>a<escape>,1]")
+        );
+
+        let text = "Reading: call:fs/read-file{path:<escape>a/b<escape>} now";
+        assert_eq!(found(text), None);
+        let options =
+            ParseOptions { extra_id_chars: HashSet::from(['/', '-']), ..Default::default() };
+        let span = find_call_span(text, &options).map(|(start, end)| &text[start..end]);
+        assert_eq!(span, Some("call:fs/read-file{path:<escape>a/b<escape>}"));
+        assert_eq!(find_call_span("call:fs/{}", &options), None);
+    }
+
+    #[test]
+    fn ignores_call_inside_escaped_prose() {
+        let text = "<escape>call:fake{}<escape> call:real{}";
+        assert_eq!(found(text), Some("call:real{}"));
+        assert_eq!(found("no calls here {}"), None);
+    }
+
+    #[test]
+    fn block_headers_inside_escaped_strings_are_left_alone() {
+        let text = "call:note{body:<escape>Steps:\ncall:x\n  k: v\n<escape>}\ncall:y\n  k: 1";
+        assert_eq!(
+            block_args_to_object(text).unwrap(),
+            "call:note{body:<escape>Steps:\ncall:x\n  k: v\n<escape>}\ncall:y{k:1}"
+        );
+    }
+
+    #[test]
+    fn blanks_comments_outside_escaped_strings() {
+        let text = "// first\ncall:f{a:<escape>//kept<escape> /* é\n */}";
+        let (blanked, comments) = blank_comments(text).unwrap();
+        assert_eq!(blanked.len(), text.len());
+        assert_eq!(blanked, "        \ncall:f{a:<escape>//kept<escape>      \n   }");
+        assert_eq!(comments, [(0, "// first".to_string()), (41, "/* é\n */".to_string())]);
+        assert_eq!(
+            blank_comments("call:f{} /* open").unwrap_err(),
+            "Unterminated block comment at byte 9"
+        );
+    }
+}
@@ -0,0 +1,684 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Argument schemas for tools and validation of tool calls against them.
+
+use crate::json_interop::json_to_value;
+use crate::value_utils::value_view_eq;
+use protobuf::proto;
+use serde_json::Map;
+use std::collections::{BTreeMap, BTreeSet};
+use tool_call_rust_proto::value::KindOneof;
+use tool_call_rust_proto::{Field, Struct, StructView, ToolCall, ToolCalls, Value, ValueView};
+
+type JsonValue = serde_json::Value;
+
+/// The JSON Schema types a value can be checked against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaType {
+    String,
+    /// Any number, including an int_value.
+    Number,
+    /// An int_value, or a number_value without a fractional part.
+    Integer,
+    Boolean,
+    Null,
+    Object,
+    Array,
+}
+
+impl SchemaType {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "string" => Some(SchemaType::String),
+            "number" => Some(SchemaType::Number),
+            "integer" => Some(SchemaType::Integer),
+            "boolean" => Some(SchemaType::Boolean),
+            "null" => Some(SchemaType::Null),
+            "object" => Some(SchemaType::Object),
+            "array" => Some(SchemaType::Array),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SchemaType::String => "string",
+            SchemaType::Number => "number",
+            SchemaType::Integer => "integer",
+            SchemaType::Boolean => "boolean",
+            SchemaType::Null => "null",
+            SchemaType::Object => "object",
+            SchemaType::Array => "array",
+        }
+    }
+
+    fn accepts(self, value: ValueView) -> bool {
+        match (self, value.kind()) {
+            (SchemaType::String, KindOneof::StringValue(_)) => true,
+            (SchemaType::Number, KindOneof::NumberValue(_) | KindOneof::IntValue(_)) => true,
+            (SchemaType::Integer, KindOneof::IntValue(_)) => true,
+            (SchemaType::Integer, KindOneof::NumberValue(n)) => n.fract() == 0.0,
+            (SchemaType::Boolean, KindOneof::BoolValue(_)) => true,
+            (SchemaType::Null, KindOneof::NullValue(_)) => true,
+            (SchemaType::Object, KindOneof::StructValue(_)) => true,
+            (SchemaType::Array, KindOneof::ListValue(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The schema of one value. Every constraint is optional; an empty schema
+/// accepts any value.
+#[derive(Clone, Debug, Default)]
+pub struct ValueSchema {
+    pub schema_type: Option<SchemaType>,
+    /// Schemas of the fields of an object. Fields not listed are allowed.
+    pub properties: BTreeMap<String, ValueSchema>,
+    /// Fields an object must have.
+    pub required: Vec<String>,
+    /// Schema of every element of an array.
+    pub items: Option<Box<ValueSchema>>,
+    /// If not empty, the value must equal one of these.
+    pub enum_values: Vec<Value>,
+    /// Inclusive bounds on a number.
+    pub minimum: Option<f64>,
+    pub maximum: Option<f64>,
+    /// Inclusive bounds on the length of a string, in characters.
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    /// The value a field of an object takes when a call leaves it out. See
+    /// [`ToolSchema::apply_defaults`].
+    pub default: Option<Value>,
+}
+
+/// The schema of the arguments of a tool.
+#[derive(Clone, Debug, Default)]
+pub struct ToolSchema {
+    pub arguments: ValueSchema,
+    /// Old names of arguments, mapped to the names the schema now uses, for
+    /// prompts written before a parameter was renamed. See
+    /// [`ToolSchema::rename_aliases`].
+    pub aliases: BTreeMap<String, String>,
+    /// One message for each keyword [`ToolSchema::from_json_schema`] did not
+    /// support and ignored, such as
+    /// `Ignoring unsupported schema keyword pattern at city`.
+    pub warnings: Vec<String>,
+}
+
+// Keywords that only document a schema and never affect validation.
+const ANNOTATIONS: &[&str] = &["$schema", "title", "description", "examples"];
+
+fn value_schema_from_json(
+    json: &Map<String, JsonValue>,
+    path: &str,
+    warnings: &mut Vec<String>,
+) -> Result<ValueSchema, String> {
+    let invalid = |keyword: &str| format!("Invalid \"{}\" in schema at {}", keyword, path);
+    let mut schema = ValueSchema::default();
+    for (keyword, value) in json {
+        match keyword.as_str() {
+            "type" => {
+                let name = value.as_str().ok_or_else(|| invalid(keyword))?;
+                schema.schema_type =
+                    Some(SchemaType::from_name(name).ok_or_else(|| invalid(keyword))?);
+            }
+            "properties" => {
+                let properties = value.as_object().ok_or_else(|| invalid(keyword))?;
+                for (name, property) in properties {
+                    let property = property.as_object().ok_or_else(|| invalid(keyword))?;
+                    let property =
+                        value_schema_from_json(property, &child_path(path, name), warnings)?;
+                    schema.properties.insert(name.clone(), property);
+                }
+            }
+            "required" => {
+                let required = value.as_array().ok_or_else(|| invalid(keyword))?;
+                for name in required {
+                    schema
+                        .required
+                        .push(name.as_str().ok_or_else(|| invalid(keyword))?.to_string());
+                }
+            }
+            "items" => {
+                let items = value.as_object().ok_or_else(|| invalid(keyword))?;
+                schema.items = Some(Box::new(value_schema_from_json(
+                    items,
+                    &format!("{}[]", path),
+                    warnings,
+                )?));
+            }
+            "enum" => {
+                for value in value.as_array().ok_or_else(|| invalid(keyword))? {
+                    schema.enum_values.push(json_to_value(value)?);
+                }
+            }
+            "minimum" => schema.minimum = Some(value.as_f64().ok_or_else(|| invalid(keyword))?),
+            "maximum" => schema.maximum = Some(value.as_f64().ok_or_else(|| invalid(keyword))?),
+            "minLength" | "maxLength" => {
+                let length = value.as_u64().ok_or_else(|| invalid(keyword))? as usize;
+                if keyword == "minLength" {
+                    schema.min_length = Some(length);
+                } else {
+                    schema.max_length = Some(length);
+                }
+            }
+            "default" => schema.default = Some(json_to_value(value)?),
+            keyword if ANNOTATIONS.contains(&keyword) => {}
+            keyword => warnings.push(format!(
+                "Ignoring unsupported schema keyword {} at {}",
+                keyword,
+                if path.is_empty() { "the top level" } else { path }
+            )),
+        }
+    }
+    Ok(schema)
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn validate_struct(schema: &ValueSchema, s: StructView, path: &str) -> Result<(), String> {
+    let field = |name: &str| s.fields().iter().find(|field| field.name() == name);
+    for name in &schema.required {
+        if field(name).is_none() {
+            return Err(format!("Missing required argument {}", child_path(path, name)));
+        }
+    }
+    for (name, property) in &schema.properties {
+        if let Some(field) = field(name) {
+            validate_value(property, field.value(), &child_path(path, name))?;
+        }
+    }
+    Ok(())
+}
+
+// Checks the minimum, maximum, minLength and maxLength of a scalar.
+fn validate_bounds(schema: &ValueSchema, value: ValueView, path: &str) -> Result<(), String> {
+    let number = match value.kind() {
+        KindOneof::NumberValue(n) => Some(n),
+        KindOneof::IntValue(n) => Some(n as f64),
+        _ => None,
+    };
+    if let Some(n) = number {
+        if let Some(minimum) = schema.minimum.filter(|minimum| n < *minimum) {
+            return Err(format!("Argument {} is {}, below the minimum of {}", path, n, minimum));
+        }
+        if let Some(maximum) = schema.maximum.filter(|maximum| n > *maximum) {
+            return Err(format!("Argument {} is {}, above the maximum of {}", path, n, maximum));
+        }
+    }
+    if let KindOneof::StringValue(s) = value.kind() {
+        let length = s.to_string().chars().count();
+        if let Some(min_length) = schema.min_length.filter(|min_length| length < *min_length) {
+            return Err(format!(
+                "Argument {} has length {}, below the minLength of {}",
+                path, length, min_length
+            ));
+        }
+        if let Some(max_length) = schema.max_length.filter(|max_length| length > *max_length) {
+            return Err(format!(
+                "Argument {} has length {}, above the maxLength of {}",
+                path, length, max_length
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Compares a value with one allowed by an enum as value_view_eq does, except
+// that an int_value and a number_value holding the same number are equal.
+fn enum_value_eq(allowed: ValueView, value: ValueView) -> bool {
+    match (allowed.kind(), value.kind()) {
+        (KindOneof::IntValue(i), KindOneof::NumberValue(n))
+        | (KindOneof::NumberValue(n), KindOneof::IntValue(i)) => {
+            n.fract() == 0.0 && (-(2f64.powi(63))..2f64.powi(63)).contains(&n) && n as i64 == i
+        }
+        _ => value_view_eq(allowed, value),
+    }
+}
+
+fn validate_value(schema: &ValueSchema, value: ValueView, path: &str) -> Result<(), String> {
+    // A reference or a nested call is resolved by the executor, after
+    // validation.
+    if let KindOneof::ReferenceValue(_) | KindOneof::CallValue(_) = value.kind() {
+        return Ok(());
+    }
+    if let Some(schema_type) = schema.schema_type {
+        if !schema_type.accepts(value) {
+            return Err(format!("Argument {} is not of type {}", path, schema_type.name()));
+        }
+    }
+    if !schema.enum_values.is_empty()
+        && !schema.enum_values.iter().any(|allowed| enum_value_eq(allowed.as_view(), value))
+    {
+        return Err(format!("Argument {} is not one of the allowed values", path));
+    }
+    validate_bounds(schema, value, path)?;
+    match value.kind() {
+        KindOneof::StructValue(s) => validate_struct(schema, s, path),
+        KindOneof::ListValue(l) => match &schema.items {
+            Some(items) => {
+                for (index, element) in l.values().iter().enumerate() {
+                    validate_value(items, element, &format!("{}[{}]", path, index))?;
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+// Fills in the defaults of the fields `s` leaves out, at any depth of nested
+// objects, adding the path of every field it fills in to `injected`.
+fn apply_struct_defaults(
+    schema: &ValueSchema,
+    s: StructView,
+    path: &str,
+    injected: &mut Vec<String>,
+) -> Struct {
+    let mut fields: Vec<Field> = s
+        .fields()
+        .iter()
+        .map(|field| {
+            let mut field = field.to_owned();
+            let name = field.name().to_string();
+            if let (Some(property), KindOneof::StructValue(nested)) =
+                (schema.properties.get(&name), field.value().kind())
+            {
+                let nested =
+                    apply_struct_defaults(property, nested, &child_path(path, &name), injected);
+                field.set_value(proto!(Value { struct_value: nested }));
+            }
+            field
+        })
+        .collect();
+    let given = |name: &str| s.fields().iter().any(|field| field.name() == name);
+    for (name, property) in &schema.properties {
+        let Some(default) = property.default.as_ref().filter(|_| !given(name)) else {
+            continue;
+        };
+        let mut field = Field::new();
+        field.set_name(name.as_str());
+        field.set_value(default.clone());
+        fields.push(field);
+        injected.push(child_path(path, name));
+    }
+    let mut object = Struct::new();
+    object.set_fields(fields.into_iter());
+    object
+}
+
+impl ToolSchema {
+    /// Builds a schema from a JSON Schema document describing the arguments
+    /// object. The `type`, `properties`, `required`, `items`, `enum`,
+    /// `minimum`, `maximum`, `minLength`, `maxLength` and `default` keywords
+    /// are supported; other keywords are ignored with a warning.
+    pub fn from_json_schema(json: &str) -> Result<ToolSchema, String> {
+        let json: JsonValue =
+            serde_json::from_str(json).map_err(|e| format!("Failed to parse schema: {}", e))?;
+        let json = json.as_object().ok_or_else(|| "Schema is not a JSON object".to_string())?;
+        let mut warnings = Vec::new();
+        let arguments = value_schema_from_json(json, "", &mut warnings)?;
+        Ok(ToolSchema { arguments, warnings, ..Default::default() })
+    }
+
+    /// Checks the arguments of `tool_call` against the schema, returning an
+    /// error naming the first argument that does not match. A call without
+    /// an arguments object is checked as if it had an empty one, so required
+    /// arguments are still reported missing.
+    pub fn validate(&self, tool_call: &ToolCall) -> Result<(), String> {
+        validate_struct(&self.arguments, tool_call.arguments(), "")
+    }
+
+    /// Adds every argument the schema gives a default to and `tool_call`
+    /// leaves out, including fields of nested objects the call does give,
+    /// after the arguments already there. Returns the paths of the arguments
+    /// added, such as `options.unit`, in the order they were added.
+    pub fn apply_defaults(&self, tool_call: &mut ToolCall) -> Vec<String> {
+        let mut injected = Vec::new();
+        let arguments =
+            apply_struct_defaults(&self.arguments, tool_call.arguments(), "", &mut injected);
+        if !injected.is_empty() {
+            tool_call.set_arguments(arguments);
+        }
+        injected
+    }
+
+    /// Renames the top-level arguments of `tool_call` found in `aliases` to
+    /// their canonical names, keeping their order, so that the call can then
+    /// be validated against the current schema. Fails without changing the
+    /// call if an argument would be given twice, as when a call has both an
+    /// alias and its canonical name.
+    pub fn rename_aliases(&self, tool_call: &mut ToolCall) -> Result<(), String> {
+        if self.aliases.is_empty() || !tool_call.has_arguments() {
+            return Ok(());
+        }
+        let mut given = BTreeMap::new();
+        let mut fields = Vec::new();
+        for field in tool_call.arguments().fields() {
+            let written = field.name().to_string();
+            let name = self.aliases.get(&written).cloned().unwrap_or_else(|| written.clone());
+            if let Some(other) = given.insert(name.clone(), written.clone()) {
+                return Err(format!("Arguments {} and {} both name {}", other, written, name));
+            }
+            let mut field = field.to_owned();
+            field.set_name(name);
+            fields.push(field);
+        }
+        let mut arguments = Struct::new();
+        arguments.set_fields(fields.into_iter());
+        tool_call.set_arguments(arguments);
+        Ok(())
+    }
+}
+
+// What the examples passed to infer_schema show of one value.
+#[derive(Default)]
+struct Inferred {
+    // None until a value is seen, and Some(None) once values of types that
+    // do not widen into one another are.
+    schema_type: Option<Option<SchemaType>>,
+    properties: BTreeMap<String, Inferred>,
+    // The fields of every object seen, or None if none was.
+    required: Option<BTreeSet<String>>,
+    items: Option<Box<Inferred>>,
+}
+
+impl Inferred {
+    fn observe_type(&mut self, schema_type: Option<SchemaType>) {
+        self.schema_type = Some(match (self.schema_type, schema_type) {
+            (None, schema_type) => schema_type,
+            (Some(a), b) if a == b => a,
+            (
+                Some(Some(SchemaType::Integer | SchemaType::Number)),
+                Some(SchemaType::Integer | SchemaType::Number),
+            ) => Some(SchemaType::Number),
+            _ => None,
+        });
+    }
+
+    fn observe_struct(&mut self, s: StructView) {
+        self.observe_type(Some(SchemaType::Object));
+        let names: BTreeSet<String> = s.fields().iter().map(|f| f.name().to_string()).collect();
+        self.required = Some(match self.required.take() {
+            Some(required) => &required & &names,
+            None => names,
+        });
+        for field in s.fields() {
+            self.properties.entry(field.name().to_string()).or_default().observe(field.value());
+        }
+    }
+
+    fn observe(&mut self, value: ValueView) {
+        let schema_type = match value.kind() {
+            KindOneof::StringValue(_) => SchemaType::String,
+            KindOneof::NumberValue(_) => SchemaType::Number,
+            KindOneof::IntValue(_) => SchemaType::Integer,
+            KindOneof::BoolValue(_) => SchemaType::Boolean,
+            KindOneof::NullValue(_) => SchemaType::Null,
+            KindOneof::StructValue(s) => return self.observe_struct(s),
+            KindOneof::ListValue(l) => {
+                for element in l.values() {
+                    self.items.get_or_insert_with(Default::default).observe(element);
+                }
+                SchemaType::Array
+            }
+            // A reference says nothing about the type of its result.
+            KindOneof::ReferenceValue(_) => return,
+            _ => return self.observe_type(None),
+        };
+        self.observe_type(Some(schema_type));
+    }
+
+    fn into_schema(self) -> ValueSchema {
+        ValueSchema {
+            schema_type: self.schema_type.flatten(),
+            properties: self
+                .properties
+                .into_iter()
+                .map(|(name, property)| (name, property.into_schema()))
+                .collect(),
+            required: self.required.unwrap_or_default().into_iter().collect(),
+            items: self.items.map(|items| Box::new(items.into_schema())),
+            ..Default::default()
+        }
+    }
+}
+
+/// Infers a starting schema for a tool from example calls to it, taking
+/// every call in `examples` as a call to the same tool. Each argument seen
+/// gets the type of its values, with integers and numbers widening to
+/// number and any other mix left untyped, and is required only if every call
+/// has it. Nested objects and array elements are inferred the same way.
+pub fn infer_schema(examples: &[ToolCalls]) -> ToolSchema {
+    let mut arguments = Inferred::default();
+    arguments.observe_type(Some(SchemaType::Object));
+    for tool_call in examples.iter().flat_map(|example| example.tool_calls()) {
+        arguments.observe_struct(tool_call.arguments());
+    }
+    ToolSchema { arguments: arguments.into_schema(), ..Default::default() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fc_as_map, ParseOptions};
+
+    const WEATHER_SCHEMA: &str = r#"{
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "object",
+        "properties": {
+            "city": {"type": "string", "description": "City name", "maxLength": 8},
+            "unit": {"enum": ["celsius", "fahrenheit"]},
+            "days": {"type": "integer", "minimum": 1, "maximum": 14},
+            "hours": {"type": "array", "items": {"type": "number"}}
+        },
+        "required": ["city"],
+        "additionalProperties": false
+    }"#;
+
+    fn call(text: &str) -> ToolCall {
+        parse_fc_as_map(text, &ParseOptions::default()).unwrap().remove("weather").unwrap()
+    }
+
+    #[test]
+    fn validates_calls_against_json_schema() {
+        let schema = ToolSchema::from_json_schema(WEATHER_SCHEMA).unwrap();
+        assert_eq!(schema.arguments.properties["days"].schema_type, Some(SchemaType::Integer));
+
+        assert_eq!(
+            schema.validate(&call(
+                "call:weather{city:<escape>Paris<escape>,unit:<escape>celsius<escape>,days:3,hours:[9,12.5]}"
+            )),
+            Ok(())
+        );
+        assert_eq!(
+            schema.validate(&call("call:weather{unit:<escape>celsius<escape>}")),
+            Err("Missing required argument city".to_string())
+        );
+        assert_eq!(
+            schema.validate(&call("call:weather{city:<escape>Paris<escape>,days:2.5}")),
+            Err("Argument days is not of type integer".to_string())
+        );
+        assert_eq!(
+            schema.validate(&call(
+                "call:weather{city:<escape>Paris<escape>,unit:<escape>kelvin<escape>}"
+            )),
+            Err("Argument unit is not one of the allowed values".to_string())
+        );
+        assert_eq!(
+            schema.validate(&call("call:weather{city:<escape>Paris<escape>,hours:[9,true]}")),
+            Err("Argument hours[1] is not of type number".to_string())
+        );
+
+        assert!(ToolSchema::from_json_schema(r#"{"type": "decimal"}"#).is_err());
+    }
+
+    #[test]
+    fn reports_ignored_keywords_as_warnings() {
+        let schema = ToolSchema::from_json_schema(WEATHER_SCHEMA).unwrap();
+        assert_eq!(
+            schema.warnings,
+            vec!["Ignoring unsupported schema keyword additionalProperties at the top level"]
+        );
+        let schema = ToolSchema::from_json_schema(
+            r#"{"properties": {"city": {"type": "string", "pattern": "^[A-Z]"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(schema.warnings, vec!["Ignoring unsupported schema keyword pattern at city"]);
+    }
+
+    #[test]
+    fn leaves_references_and_nested_calls_to_the_executor() {
+        let schema = ToolSchema::from_json_schema(WEATHER_SCHEMA).unwrap();
+        let options = ParseOptions { nested_calls: true, ..Default::default() };
+        let call = parse_fc_as_map(
+            "call:weather{city:call:locate{ip:$ip},days:$days,unit:<escape>celsius<escape>}",
+            &options,
+        )
+        .unwrap()
+        .remove("weather")
+        .unwrap();
+        assert_eq!(schema.validate(&call), Ok(()));
+    }
+
+    #[test]
+    fn matches_enum_numbers_by_value() {
+        let schema =
+            ToolSchema::from_json_schema(r#"{"properties": {"level": {"enum": [1, 2.5, 3.0]}}}"#)
+                .unwrap();
+        let integers = ParseOptions { integer_values: true, ..Default::default() };
+        let call_with = |text: &str, options: &ParseOptions| {
+            parse_fc_as_map(text, options).unwrap().remove("weather").unwrap()
+        };
+        for text in ["call:weather{level:1}", "call:weather{level:3}", "call:weather{level:2.5}"] {
+            assert_eq!(
+                schema.validate(&call_with(text, &ParseOptions::default())),
+                Ok(()),
+                "{}",
+                text
+            );
+            assert_eq!(schema.validate(&call_with(text, &integers)), Ok(()), "{}", text);
+        }
+        assert_eq!(
+            schema.validate(&call_with("call:weather{level:2}", &integers)),
+            Err("Argument level is not one of the allowed values".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_values_out_of_bounds() {
+        let schema = ToolSchema::from_json_schema(WEATHER_SCHEMA).unwrap();
+        assert_eq!(
+            schema.validate(&call("call:weather{city:<escape>Paris<escape>,days:0}")),
+            Err("Argument days is 0, below the minimum of 1".to_string())
+        );
+        assert_eq!(
+            schema.validate(&call("call:weather{city:<escape>Paris<escape>,days:15}")),
+            Err("Argument days is 15, above the maximum of 14".to_string())
+        );
+        assert_eq!(
+            schema.validate(&call("call:weather{city:<escape>Zürich-Nord<escape>}")),
+            Err("Argument city has length 11, above the maxLength of 8".to_string())
+        );
+        assert_eq!(
+            schema.validate(&call("call:weather{city:<escape>Zürich<escape>,days:14}")),
+            Ok(())
+        );
+        assert!(ToolSchema::from_json_schema(r#"{"maxLength": -1}"#).is_err());
+    }
+
+    #[test]
+    fn aliases_are_renamed_before_validation() {
+        let mut schema = ToolSchema::from_json_schema(
+            r#"{"type": "object", "properties": {"q": {"type": "string"}, "limit": {"type": "number"}}, "required": ["limit"]}"#,
+        )
+        .unwrap();
+        schema.aliases.insert("max".to_string(), "limit".to_string());
+        let parse = |text| {
+            parse_fc_as_map(text, &ParseOptions::default()).unwrap().remove("search").unwrap()
+        };
+
+        let mut search = parse("call:search{q:<escape>cats<escape>,max:5}");
+        assert_eq!(schema.validate(&search), Err("Missing required argument limit".to_string()));
+        schema.rename_aliases(&mut search).unwrap();
+        let names: Vec<String> =
+            search.arguments().fields().iter().map(|f| f.name().to_string()).collect();
+        assert_eq!(names, ["q", "limit"]);
+        assert_eq!(schema.validate(&search), Ok(()));
+
+        let mut both = parse("call:search{limit:5,max:10}");
+        assert_eq!(
+            schema.rename_aliases(&mut both),
+            Err("Arguments limit and max both name limit".to_string())
+        );
+        assert_eq!(both.arguments().fields().get(1).unwrap().name(), "max");
+    }
+
+    #[test]
+    fn absent_arguments_are_checked_like_empty_ones() {
+        let schema = ToolSchema::from_json_schema(
+            r#"{"type": "object", "properties": {"q": {"type": "string"}}, "required": ["q"]}"#,
+        )
+        .unwrap();
+        let mut search = ToolCall::new();
+        search.set_name("search");
+        assert!(!search.has_arguments());
+        let missing = Err("Missing required argument q".to_string());
+        assert_eq!(schema.validate(&search), missing);
+        let parsed =
+            parse_fc_as_map("call:search{}, call:other{x:1}", &ParseOptions::default()).unwrap();
+        assert_eq!(schema.validate(&parsed["search"]), missing);
+        assert_eq!(schema.validate(&parsed["other"]), missing);
+    }
+
+    #[test]
+    fn infers_types_and_optional_fields_from_examples() {
+        let options = ParseOptions { integer_values: true, ..Default::default() };
+        let examples: Vec<ToolCalls> = [
+            "call:weather{city:<escape>Paris<escape>,days:3,temp:20,opts:{units:[<escape>c<escape>]}}",
+            "call:weather{city:<escape>Oslo<escape>,days:5,temp:-1.5}",
+            "call:weather{days:1,temp:7,opts:{units:[],verbose:true}}",
+        ]
+        .iter()
+        .map(|text| {
+            let result = crate::parse_fc_expression_with_options(text, &options);
+            ToolCalls::parse(&result.serialized_tool_calls).unwrap()
+        })
+        .collect();
+
+        let schema = infer_schema(&examples).arguments;
+        assert_eq!(schema.schema_type, Some(SchemaType::Object));
+        assert_eq!(schema.required, ["days", "temp"]);
+        assert_eq!(schema.properties["city"].schema_type, Some(SchemaType::String));
+        assert_eq!(schema.properties["days"].schema_type, Some(SchemaType::Integer));
+        assert_eq!(schema.properties["temp"].schema_type, Some(SchemaType::Number));
+        let opts = &schema.properties["opts"];
+        assert_eq!(opts.required, ["units"]);
+        let units = opts.properties["units"].items.as_ref().unwrap();
+        assert_eq!(units.schema_type, Some(SchemaType::String));
+
+        let schema = ToolSchema { arguments: schema, ..Default::default() };
+        for example in &examples {
+            assert_eq!(schema.validate(&example.tool_calls().get(0).unwrap().to_owned()), Ok(()));
+        }
+    }
+}
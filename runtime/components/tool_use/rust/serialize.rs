@@ -0,0 +1,355 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rendering of ToolCalls back into FC expressions that parse to the same
+//! values.
+
+use crate::scan::ESCAPE;
+use tool_call_rust_proto::value::KindOneof;
+use tool_call_rust_proto::{StructView, ToolCallView, ToolCalls, ValueView};
+
+/// How number_value is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// The shortest text that parses back to the same f64, so 0.3 is `0.3`.
+    #[default]
+    Shortest,
+    /// Round to this many significant digits first, so that a computed
+    /// 0.30000000000000004 is written as `0.3`. The value read back is the
+    /// rounded one.
+    SignificantDigits(usize),
+}
+
+/// Options controlling how tool calls are serialized.
+#[derive(Clone, Debug, Default)]
+pub struct SerializeOptions {
+    pub float_format: FloatFormat,
+}
+
+fn write_key(key: &str, out: &mut String) -> Result<(), String> {
+    let mut bytes = key.bytes();
+    let valid = bytes.next().is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_');
+    if !valid {
+        return Err(format!("Key {:?} is not an identifier", key));
+    }
+    out.push_str(key);
+    Ok(())
+}
+
+// Debug formatting of an f64 is the shortest text that parses back to the
+// same value, and switches to an exponent for very large or small
+// magnitudes. It keeps a `.0` on whole numbers, so that integer_values reads
+// them back as number_value. The grammar has no fraction and exponent in the
+// same number, so `1.5e-7` is written as `15e-8`.
+fn write_number(n: f64, options: &SerializeOptions, out: &mut String) -> Result<(), String> {
+    if !n.is_finite() {
+        return Err(format!("Number {} has no FC representation", n));
+    }
+    let n = match options.float_format {
+        FloatFormat::Shortest => n,
+        FloatFormat::SignificantDigits(0) => {
+            return Err("Numbers need at least one significant digit".to_string())
+        }
+        FloatFormat::SignificantDigits(digits) => {
+            format!("{:.*e}", digits - 1, n).parse().map_err(|e| format!("{}: {}", n, e))?
+        }
+    };
+    let text = format!("{:?}", n);
+    match text.split_once('e') {
+        Some((mantissa, exponent)) if mantissa.contains('.') => {
+            let (whole, fraction) = mantissa.split_once('.').unwrap();
+            let exponent: i32 = exponent.parse().map_err(|e| format!("{}: {}", text, e))?;
+            out.push_str(whole);
+            out.push_str(fraction);
+            out.push_str(&format!("e{}", exponent - fraction.len() as i32));
+        }
+        _ => out.push_str(&text),
+    }
+    Ok(())
+}
+
+// Writes an object, starting with a `**$name` entry for each spread.
+fn write_struct(
+    s: StructView,
+    spread_refs: &[String],
+    options: &SerializeOptions,
+    out: &mut String,
+) -> Result<(), String> {
+    out.push('{');
+    for (index, name) in spread_refs.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        out.push_str("**$");
+        write_key(name, out)?;
+    }
+    for (index, field) in s.fields().iter().enumerate() {
+        if index > 0 || !spread_refs.is_empty() {
+            out.push(',');
+        }
+        write_key(&field.name().to_string(), out)?;
+        out.push(':');
+        write_value(field.value(), options, out)?;
+    }
+    out.push('}');
+    Ok(())
+}
+
+fn write_value(
+    value: ValueView,
+    options: &SerializeOptions,
+    out: &mut String,
+) -> Result<(), String> {
+    match value.kind() {
+        KindOneof::NumberValue(n) => write_number(n, options, out)?,
+        KindOneof::IntValue(n) => out.push_str(&n.to_string()),
+        KindOneof::StringValue(s) => {
+            let s = s.to_string();
+            if s.contains(ESCAPE) {
+                return Err(format!("String {:?} contains {}", s, ESCAPE));
+            }
+            out.push_str(ESCAPE);
+            out.push_str(&s);
+            out.push_str(ESCAPE);
+        }
+        KindOneof::BoolValue(b) => out.push_str(if b { "true" } else { "false" }),
+        KindOneof::ReferenceValue(name) => {
+            out.push('$');
+            out.push_str(&name.to_string());
+        }
+        KindOneof::StructValue(s) => write_struct(s, &[], options, out)?,
+        KindOneof::ListValue(l) => {
+            out.push('[');
+            for (index, element) in l.values().iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_value(element, options, out)?;
+            }
+            out.push(']');
+        }
+        // The grammar only takes a score or a verb with a top-level call.
+        KindOneof::CallValue(call) if call.has_confidence() => {
+            return Err(format!(
+                "Confidence of nested call {} has no FC representation",
+                call.name()
+            ))
+        }
+        KindOneof::CallValue(call) if !call.dispatch_verb().is_empty() => {
+            return Err(format!(
+                "Dispatch verb of nested call {} has no FC representation",
+                call.name()
+            ))
+        }
+        KindOneof::CallValue(call) => write_tool_call(call, options, out)?,
+        KindOneof::BytesValue(_) => return Err("Bytes have no FC representation".to_string()),
+        _ => out.push_str("null"),
+    }
+    Ok(())
+}
+
+fn write_tool_call(
+    tool_call: ToolCallView,
+    options: &SerializeOptions,
+    out: &mut String,
+) -> Result<(), String> {
+    // Comments go on lines of their own before the call.
+    for comment in tool_call.comments() {
+        out.push_str(&comment.to_string());
+        out.push('\n');
+    }
+    if !tool_call.dispatch_verb().is_empty() {
+        write_key(&tool_call.dispatch_verb().to_string(), out)?;
+        out.push(' ');
+    }
+    out.push_str("call:");
+    write_key(&tool_call.name().to_string(), out)?;
+    // The grammar requires an object, so a call without arguments gets `{}`.
+    let spread_refs: Vec<String> =
+        tool_call.spread_refs().iter().map(|name| name.to_string()).collect();
+    write_struct(tool_call.arguments(), &spread_refs, options, out)?;
+    if tool_call.has_confidence() {
+        out.push_str(" @");
+        write_number(tool_call.confidence(), options, out)?;
+    }
+    Ok(())
+}
+
+/// Renders `tool_calls` as comma-separated `call:name{...}` expressions.
+/// int_value is written without a decimal point and number_value always
+/// with one or with an exponent, so that parsing the text with
+/// integer_values gives back the same kinds. Fails on values the grammar
+/// cannot express: non-finite numbers, bytes, strings containing `<escape>`,
+/// and keys or names that are not identifiers. Captured comments are written
+/// before their call, so parsing with capture_comments keeps them, a
+/// dispatch verb before its call and a confidence after it as `@0.87`, for
+/// dispatch_verbs and call_confidence, and a call_value in place as a nested
+/// call.
+pub fn serialize_fc_expression(tool_calls: &ToolCalls) -> Result<String, String> {
+    serialize_fc_expression_with_options(tool_calls, &SerializeOptions::default())
+}
+
+/// Like [`serialize_fc_expression`], with numbers written as
+/// `options.float_format` says.
+pub fn serialize_fc_expression_with_options(
+    tool_calls: &ToolCalls,
+    options: &SerializeOptions,
+) -> Result<String, String> {
+    let mut out = String::new();
+    for (index, tool_call) in tool_calls.tool_calls().iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        write_tool_call(tool_call, options, &mut out)?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fc_expression_with_options, ParseOptions};
+    use protobuf::{prelude::*, proto};
+    use tool_call_rust_proto::value::KindCase;
+    use tool_call_rust_proto::{Field, ToolCall, Value};
+
+    fn parse(text: &str, options: &ParseOptions) -> ToolCalls {
+        let result = parse_fc_expression_with_options(text, options);
+        assert!(result.is_ok, "{}: {}", text, result.error);
+        ToolCalls::parse(&result.serialized_tool_calls).unwrap()
+    }
+
+    #[test]
+    fn integers_and_floats_keep_their_kinds() {
+        let options = ParseOptions { integer_values: true, ..Default::default() };
+        let tool_calls = parse(
+            "call:f{count:3,ratio:3.0,big:1e300,tiny:-0.00000015,s:<escape>a b<escape>,o:{l:[true,null,$r]}},call:g{}",
+            &options,
+        );
+
+        let text = serialize_fc_expression(&tool_calls).unwrap();
+        assert_eq!(
+            text,
+            "call:f{count:3,ratio:3.0,big:1e300,tiny:-15e-8,s:<escape>a b<escape>,o:{l:[true,null,$r]}},call:g{}"
+        );
+        let reparsed = parse(&text, &options);
+        assert_eq!(reparsed.serialize().unwrap(), tool_calls.serialize().unwrap());
+        let fields = reparsed.tool_calls().get(0).unwrap().arguments().fields();
+        assert_eq!(fields.get(0).unwrap().value().kind_case(), KindCase::IntValue);
+        assert_eq!(fields.get(1).unwrap().value().kind_case(), KindCase::NumberValue);
+    }
+
+    #[test]
+    fn float_format_controls_the_digits_written() {
+        let options = ParseOptions::default();
+        let tool_calls = parse("call:f{a:0.3,b:0.30000000000000004,c:123456.789}", &options);
+
+        let text = serialize_fc_expression(&tool_calls).unwrap();
+        assert_eq!(text, "call:f{a:0.3,b:0.30000000000000004,c:123456.789}");
+        let reparsed = parse(&text, &options);
+        let fields = reparsed.tool_calls().get(0).unwrap().arguments().fields();
+        assert_eq!(fields.get(0).unwrap().value().number_value(), 0.3);
+
+        let rounded = SerializeOptions { float_format: FloatFormat::SignificantDigits(4) };
+        assert_eq!(
+            serialize_fc_expression_with_options(&tool_calls, &rounded).unwrap(),
+            "call:f{a:0.3,b:0.3,c:123500.0}"
+        );
+        let none = SerializeOptions { float_format: FloatFormat::SignificantDigits(0) };
+        assert!(serialize_fc_expression_with_options(&tool_calls, &none).is_err());
+    }
+
+    #[test]
+    fn captured_comments_survive_a_round_trip() {
+        let options = ParseOptions { capture_comments: true, ..Default::default() };
+        let tool_calls = parse(
+            "// look it up first\ncall:search{q:<escape>a // b<escape>}, call:open{/* the top hit */ n:1}",
+            &options,
+        );
+
+        let text = serialize_fc_expression(&tool_calls).unwrap();
+        assert_eq!(
+            text,
+            "// look it up first\ncall:search{q:<escape>a // b<escape>},/* the top hit */\ncall:open{n:1.0}"
+        );
+        let reparsed = parse(&text, &options);
+        assert_eq!(reparsed.serialize().unwrap(), tool_calls.serialize().unwrap());
+        let comments: Vec<String> = reparsed
+            .tool_calls()
+            .get(0)
+            .unwrap()
+            .comments()
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(comments, ["// look it up first"]);
+    }
+
+    #[test]
+    fn nested_calls_are_written_in_place() {
+        let options = ParseOptions { nested_calls: true, ..Default::default() };
+        let text = "call:outer{inner:call:search{q:<escape>x<escape>}}";
+        let tool_calls = parse(text, &options);
+        assert_eq!(serialize_fc_expression(&tool_calls).unwrap(), text);
+    }
+
+    #[test]
+    fn confidence_is_written_after_the_call() {
+        let options = ParseOptions { call_confidence: true, ..Default::default() };
+        let text = "call:search{q:<escape>x<escape>} @0.87,call:open{} @1.0,call:close{}";
+        let tool_calls = parse(text, &options);
+        assert_eq!(serialize_fc_expression(&tool_calls).unwrap(), text);
+
+        let mut inner = ToolCall::new();
+        inner.set_name("g");
+        inner.set_confidence(0.5);
+        let mut field = Field::new();
+        field.set_name("n");
+        field.set_value(proto!(Value { call_value: inner }));
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(ToolCall::new());
+        tool_calls.tool_calls_mut().get_mut(0).unwrap().set_name("f");
+        tool_calls.tool_calls_mut().get_mut(0).unwrap().arguments_mut().fields_mut().push(field);
+        let err = serialize_fc_expression(&tool_calls).unwrap_err();
+        assert_eq!(err, "Confidence of nested call g has no FC representation");
+    }
+
+    #[test]
+    fn dispatch_verbs_are_written_before_the_call() {
+        let options = ParseOptions {
+            dispatch_verbs: ["INVOKE"].into_iter().map(String::from).collect(),
+            capture_comments: true,
+            call_confidence: true,
+            ..Default::default()
+        };
+        let text = "// first\nINVOKE call:search{} @0.5,call:open{}";
+        let tool_calls = parse(text, &options);
+        assert_eq!(serialize_fc_expression(&tool_calls).unwrap(), text);
+
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(ToolCall::new());
+        tool_calls.tool_calls_mut().get_mut(0).unwrap().set_name("f");
+        tool_calls.tool_calls_mut().get_mut(0).unwrap().set_dispatch_verb("two words");
+        assert!(serialize_fc_expression(&tool_calls).is_err());
+    }
+
+    #[test]
+    fn spreads_come_before_the_arguments() {
+        let tool_calls = parse("call:f{x:1,**$a,**$b}", &ParseOptions::default());
+        assert_eq!(serialize_fc_expression(&tool_calls).unwrap(), "call:f{**$a,**$b,x:1.0}");
+        let tool_calls = parse("call:f{**$a}", &ParseOptions::default());
+        assert_eq!(serialize_fc_expression(&tool_calls).unwrap(), "call:f{**$a}");
+    }
+}
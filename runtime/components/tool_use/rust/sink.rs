@@ -0,0 +1,488 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The value backend driven by the parse tree walk.
+
+use protobuf::proto;
+use std::collections::HashMap;
+use std::ops::Range;
+use tool_call_rust_proto::value::KindOneof;
+use tool_call_rust_proto::{Field, ListValue, NullValue, Struct, ToolCall, ToolCalls, Value};
+
+/// Builds the output of a parse. The walk calls these methods bottom-up: the
+/// values of an object or array are built before they are pushed into it, and
+/// `function_call` is called once per call with its finished arguments.
+pub trait ValueSink {
+    type Value;
+    type Object;
+    type Array;
+
+    fn begin_object(&mut self) -> Self::Object;
+    fn push_field(&mut self, object: &mut Self::Object, key: String, value: Self::Value);
+    /// Called with the byte ranges of each argument's key and value in the
+    /// parsed text and its path, such as `a.b[2].c`, before the call's
+    /// `function_call`.
+    fn field_spans(&mut self, _path: &str, _key: Range<usize>, _value: Range<usize>) {}
+    fn end_object(&mut self, object: Self::Object) -> Self::Value;
+
+    fn begin_array(&mut self) -> Self::Array;
+    fn push_element(&mut self, array: &mut Self::Array, value: Self::Value);
+    fn end_array(&mut self, array: Self::Array) -> Self::Value;
+
+    fn scalar_string(&mut self, s: &str) -> Self::Value;
+    /// Like `scalar_string` when `s` is exactly `span` of the parsed text,
+    /// for sinks that borrow from the input instead of copying.
+    fn scalar_string_at(&mut self, s: &str, _span: Range<usize>) -> Self::Value {
+        self.scalar_string(s)
+    }
+    fn scalar_number(&mut self, n: f64) -> Self::Value;
+    /// An integer literal, when the parser keeps integers apart. Sinks that
+    /// do not override it get it as a number.
+    fn scalar_int(&mut self, n: i64) -> Self::Value {
+        self.scalar_number(n as f64)
+    }
+    fn scalar_bool(&mut self, b: bool) -> Self::Value;
+    fn scalar_null(&mut self) -> Self::Value;
+    /// A `$name` reference to an earlier result; `name` excludes the `$`.
+    /// Sinks that do not override it get the string `$name`.
+    fn scalar_reference(&mut self, name: &str) -> Self::Value {
+        self.scalar_string(&format!("${}", name))
+    }
+    /// A call nested in value position, when the parser accepts them. Sinks
+    /// that do not override it get an object with the call's name as its
+    /// only key, and drop the spreads.
+    fn call_value(
+        &mut self,
+        name: String,
+        arguments: Self::Object,
+        _spread_refs: Vec<String>,
+    ) -> Self::Value {
+        let mut object = self.begin_object();
+        let arguments = self.end_object(arguments);
+        self.push_field(&mut object, name, arguments);
+        self.end_object(object)
+    }
+
+    /// The kind of `value`, such as `string` or `object`, for checks that
+    /// compare values, like homogeneous_arrays. Sinks that do not override it
+    /// return None and are not checked.
+    fn kind_name(&self, _value: &Self::Value) -> Option<&'static str> {
+        None
+    }
+
+    /// Called for each call. `arguments` is None for a call without an
+    /// object and an empty object for `{}`.
+    fn function_call(&mut self, name: String, arguments: Option<Self::Object>);
+
+    /// Called just before the call that `comments` belong to, when the parser
+    /// captures comments. Sinks that do not override it drop them.
+    fn comments(&mut self, _comments: Vec<String>) {}
+
+    /// Called just before the call that `confidence` was written after, when
+    /// the parser reads confidence scores. Sinks that do not override it drop
+    /// it.
+    fn confidence(&mut self, _confidence: f64) {}
+
+    /// Called just before the call that `verb` was written in front of, when
+    /// the parser is given dispatch verbs. Sinks that do not override it drop
+    /// it.
+    fn dispatch_verb(&mut self, _verb: String) {}
+
+    /// Called instead of `function_call` for a call whose arguments include
+    /// `**$name` spreads, with the names in order. Sinks that do not
+    /// override it drop the spreads.
+    fn function_call_with_spreads(
+        &mut self,
+        name: String,
+        arguments: Option<Self::Object>,
+        _spread_refs: Vec<String>,
+    ) {
+        self.function_call(name, arguments)
+    }
+}
+
+/// The sink behind parse_fc_expression, building a ToolCalls proto.
+#[derive(Default)]
+pub(crate) struct ProtoSink {
+    pub(crate) tool_calls: ToolCalls,
+    // Comments of the call about to be built.
+    comments: Vec<String>,
+    confidence: Option<f64>,
+    dispatch_verb: String,
+}
+
+impl ValueSink for ProtoSink {
+    type Value = Value;
+    type Object = Struct;
+    type Array = ListValue;
+
+    fn begin_object(&mut self) -> Struct {
+        Struct::new()
+    }
+
+    fn push_field(&mut self, object: &mut Struct, key: String, value: Value) {
+        let mut field = Field::new();
+        field.set_name(key);
+        field.set_value(value);
+        object.fields_mut().push(field);
+    }
+
+    fn end_object(&mut self, object: Struct) -> Value {
+        proto!(Value { struct_value: object })
+    }
+
+    fn begin_array(&mut self) -> ListValue {
+        ListValue::new()
+    }
+
+    fn push_element(&mut self, array: &mut ListValue, value: Value) {
+        array.values_mut().push(value);
+    }
+
+    fn end_array(&mut self, array: ListValue) -> Value {
+        proto!(Value { list_value: array })
+    }
+
+    fn scalar_string(&mut self, s: &str) -> Value {
+        proto!(Value { string_value: s })
+    }
+
+    fn scalar_number(&mut self, n: f64) -> Value {
+        proto!(Value { number_value: n })
+    }
+
+    fn scalar_int(&mut self, n: i64) -> Value {
+        proto!(Value { int_value: n })
+    }
+
+    fn scalar_bool(&mut self, b: bool) -> Value {
+        proto!(Value { bool_value: b })
+    }
+
+    fn scalar_null(&mut self) -> Value {
+        proto!(Value { null_value: NullValue::default() })
+    }
+
+    fn scalar_reference(&mut self, name: &str) -> Value {
+        proto!(Value { reference_value: name })
+    }
+
+    fn call_value(&mut self, name: String, arguments: Struct, spread_refs: Vec<String>) -> Value {
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name(name);
+        tool_call.set_arguments(arguments);
+        tool_call.set_spread_refs(spread_refs.into_iter());
+        proto!(Value { call_value: tool_call })
+    }
+
+    fn kind_name(&self, value: &Value) -> Option<&'static str> {
+        Some(match value.kind() {
+            KindOneof::StringValue(_) => "string",
+            KindOneof::NumberValue(_) => "number",
+            KindOneof::IntValue(_) => "int",
+            KindOneof::BoolValue(_) => "bool",
+            KindOneof::NullValue(_) => "null",
+            KindOneof::ReferenceValue(_) => "reference",
+            KindOneof::StructValue(_) => "object",
+            KindOneof::ListValue(_) => "array",
+            KindOneof::CallValue(_) => "call",
+            KindOneof::BytesValue(_) => "bytes",
+            _ => "unset",
+        })
+    }
+
+    fn function_call(&mut self, name: String, arguments: Option<Struct>) {
+        self.function_call_with_spreads(name, arguments, Vec::new());
+    }
+
+    fn comments(&mut self, comments: Vec<String>) {
+        self.comments = comments;
+    }
+
+    fn confidence(&mut self, confidence: f64) {
+        self.confidence = Some(confidence);
+    }
+
+    fn dispatch_verb(&mut self, verb: String) {
+        self.dispatch_verb = verb;
+    }
+
+    fn function_call_with_spreads(
+        &mut self,
+        name: String,
+        arguments: Option<Struct>,
+        spread_refs: Vec<String>,
+    ) {
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name(name);
+        if let Some(arguments) = arguments {
+            tool_call.set_arguments(arguments);
+        }
+        tool_call.set_spread_refs(spread_refs.into_iter());
+        tool_call.set_comments(std::mem::take(&mut self.comments).into_iter());
+        if let Some(confidence) = self.confidence.take() {
+            tool_call.set_confidence(confidence);
+        }
+        tool_call.set_dispatch_verb(std::mem::take(&mut self.dispatch_verb));
+        self.tool_calls.tool_calls_mut().push(tool_call);
+    }
+}
+
+/// Where an argument was written in the parsed text, as byte ranges.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldSpans {
+    pub key: Range<usize>,
+    pub value: Range<usize>,
+}
+
+/// A ProtoSink that also records the spans of every argument, by path, for
+/// each call.
+#[derive(Default)]
+pub(crate) struct SpanSink {
+    pub(crate) proto: ProtoSink,
+    pub(crate) spans: Vec<HashMap<String, FieldSpans>>,
+    current: HashMap<String, FieldSpans>,
+}
+
+impl ValueSink for SpanSink {
+    type Value = Value;
+    type Object = Struct;
+    type Array = ListValue;
+
+    fn begin_object(&mut self) -> Struct {
+        self.proto.begin_object()
+    }
+
+    fn push_field(&mut self, object: &mut Struct, key: String, value: Value) {
+        self.proto.push_field(object, key, value)
+    }
+
+    fn field_spans(&mut self, path: &str, key: Range<usize>, value: Range<usize>) {
+        self.current.insert(path.to_string(), FieldSpans { key, value });
+    }
+
+    fn end_object(&mut self, object: Struct) -> Value {
+        self.proto.end_object(object)
+    }
+
+    fn begin_array(&mut self) -> ListValue {
+        self.proto.begin_array()
+    }
+
+    fn push_element(&mut self, array: &mut ListValue, value: Value) {
+        self.proto.push_element(array, value)
+    }
+
+    fn end_array(&mut self, array: ListValue) -> Value {
+        self.proto.end_array(array)
+    }
+
+    fn scalar_string(&mut self, s: &str) -> Value {
+        self.proto.scalar_string(s)
+    }
+
+    fn scalar_number(&mut self, n: f64) -> Value {
+        self.proto.scalar_number(n)
+    }
+
+    fn scalar_int(&mut self, n: i64) -> Value {
+        self.proto.scalar_int(n)
+    }
+
+    fn scalar_bool(&mut self, b: bool) -> Value {
+        self.proto.scalar_bool(b)
+    }
+
+    fn scalar_null(&mut self) -> Value {
+        self.proto.scalar_null()
+    }
+
+    fn scalar_reference(&mut self, name: &str) -> Value {
+        self.proto.scalar_reference(name)
+    }
+
+    fn call_value(&mut self, name: String, arguments: Struct, spread_refs: Vec<String>) -> Value {
+        self.proto.call_value(name, arguments, spread_refs)
+    }
+
+    fn kind_name(&self, value: &Value) -> Option<&'static str> {
+        self.proto.kind_name(value)
+    }
+
+    fn function_call(&mut self, name: String, arguments: Option<Struct>) {
+        self.function_call_with_spreads(name, arguments, Vec::new());
+    }
+
+    fn comments(&mut self, comments: Vec<String>) {
+        self.proto.comments(comments)
+    }
+
+    fn confidence(&mut self, confidence: f64) {
+        self.proto.confidence(confidence)
+    }
+
+    fn dispatch_verb(&mut self, verb: String) {
+        self.proto.dispatch_verb(verb)
+    }
+
+    fn function_call_with_spreads(
+        &mut self,
+        name: String,
+        arguments: Option<Struct>,
+        spread_refs: Vec<String>,
+    ) {
+        self.proto.function_call_with_spreads(name, arguments, spread_refs);
+        self.spans.push(std::mem::take(&mut self.current));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fc_expression_with_sink, ParseOptions};
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct CountingSink {
+        objects: usize,
+        arrays: usize,
+        fields: usize,
+        scalars: usize,
+        calls: Vec<(String, usize)>,
+    }
+
+    impl ValueSink for CountingSink {
+        type Value = ();
+        type Object = usize;
+        type Array = ();
+
+        fn begin_object(&mut self) -> usize {
+            0
+        }
+        fn push_field(&mut self, object: &mut usize, _key: String, _value: ()) {
+            *object += 1;
+            self.fields += 1;
+        }
+        fn end_object(&mut self, _object: usize) {
+            self.objects += 1;
+        }
+        fn begin_array(&mut self) {}
+        fn push_element(&mut self, _array: &mut (), _value: ()) {}
+        fn end_array(&mut self, _array: ()) {
+            self.arrays += 1;
+        }
+        fn scalar_string(&mut self, _s: &str) {
+            self.scalars += 1;
+        }
+        fn scalar_number(&mut self, _n: f64) {
+            self.scalars += 1;
+        }
+        fn scalar_bool(&mut self, _b: bool) {
+            self.scalars += 1;
+        }
+        fn scalar_null(&mut self) {
+            self.scalars += 1;
+        }
+        fn function_call(&mut self, name: String, arguments: Option<usize>) {
+            self.calls.push((name, arguments.unwrap_or_default()));
+        }
+    }
+
+    #[test]
+    fn counting_sink_sees_nested_call() {
+        let sink = parse_fc_expression_with_sink(
+            "call:f{a:{b:[1,true,{c:null}]},d:<escape>x<escape>}",
+            &ParseOptions::default(),
+            CountingSink::default(),
+        )
+        .unwrap();
+        assert_eq!(sink.calls, vec![("f".to_string(), 2)]);
+        assert_eq!(sink.objects, 2);
+        assert_eq!(sink.arrays, 1);
+        assert_eq!(sink.fields, 4);
+        assert_eq!(sink.scalars, 4);
+
+        // Integers and references fall back to scalar_number and
+        // scalar_string.
+        let options = ParseOptions { integer_values: true, ..Default::default() };
+        let sink =
+            parse_fc_expression_with_sink("call:f{a:1,b:$x}", &options, CountingSink::default())
+                .unwrap();
+        assert_eq!(sink.scalars, 2);
+    }
+
+    // Shares one Rc<str> between equal string values, as a caller wanting
+    // interned strings would.
+    #[derive(Default)]
+    struct InterningSink {
+        strings: HashMap<String, Rc<str>>,
+        values: Vec<Rc<str>>,
+    }
+
+    impl ValueSink for InterningSink {
+        type Value = Option<Rc<str>>;
+        type Object = ();
+        type Array = ();
+
+        fn begin_object(&mut self) {}
+        fn push_field(&mut self, _object: &mut (), _key: String, _value: Option<Rc<str>>) {}
+        fn end_object(&mut self, _object: ()) -> Option<Rc<str>> {
+            None
+        }
+        fn begin_array(&mut self) {}
+        fn push_element(&mut self, _array: &mut (), value: Option<Rc<str>>) {
+            self.values.extend(value);
+        }
+        fn end_array(&mut self, _array: ()) -> Option<Rc<str>> {
+            None
+        }
+        fn scalar_string(&mut self, s: &str) -> Option<Rc<str>> {
+            if let Some(value) = self.strings.get(s) {
+                return Some(value.clone());
+            }
+            let value: Rc<str> = Rc::from(s);
+            self.strings.insert(s.to_string(), value.clone());
+            Some(value)
+        }
+        fn scalar_number(&mut self, _n: f64) -> Option<Rc<str>> {
+            None
+        }
+        fn scalar_bool(&mut self, _b: bool) -> Option<Rc<str>> {
+            None
+        }
+        fn scalar_null(&mut self) -> Option<Rc<str>> {
+            None
+        }
+        fn function_call(&mut self, _name: String, _arguments: Option<()>) {}
+    }
+
+    #[test]
+    fn interning_sink_shares_repeated_strings() {
+        let values: Vec<&str> = (0..2000).map(|i| ["/tmp/a", "ENUM_B"][i % 2]).collect();
+        let elements: Vec<String> =
+            values.iter().map(|value| format!("<escape>{}<escape>", value)).collect();
+        let text = format!("call:f{{paths:[{}]}}", elements.join(","));
+        let sink = parse_fc_expression_with_sink(
+            &text,
+            &ParseOptions::default(),
+            InterningSink::default(),
+        )
+        .unwrap();
+
+        // Two backing strings for 2000 values, each equal to what was written.
+        assert_eq!(sink.strings.len(), 2);
+        assert!(sink.values.iter().map(|value| &**value).eq(values.iter().copied()));
+        assert!(Rc::ptr_eq(&sink.values[0], &sink.values[2]));
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Incremental scanner over a streamed FC expression, for UIs that render
+//! tool arguments while the model is still generating them.
+
+use protobuf::proto;
+use tool_call_rust_proto::{NullValue, Value};
+
+const ESCAPE: &str = "<escape>";
+
+/// Events emitted by [`FcStreamParser`] as soon as the text they describe is
+/// complete. `ScalarValue` is only emitted for top-level arguments; nested
+/// objects and arrays are skipped over.
+#[derive(Debug)]
+pub enum FcStreamEvent {
+    FunctionStart(String),
+    KeySeen(String),
+    ScalarValue(String, Value),
+    FunctionEnd,
+}
+
+#[derive(Debug)]
+enum Token {
+    Punct(char),
+    Str(String),
+    Word(String),
+}
+
+#[derive(Debug)]
+enum State {
+    Call,
+    // After a call, where one comma may come before the next.
+    AfterCall,
+    CallColon,
+    Name,
+    ArgsOpen,
+    Key,
+    KeyColon(String),
+    Value(String),
+    Nested(usize),
+    AfterValue,
+}
+
+/// Only the text of the call in progress is buffered: whatever precedes it
+/// is dropped as soon as the previous call ends.
+pub struct FcStreamParser {
+    buffer: String,
+    pos: usize,
+    // Bytes of the stream dropped from the front of `buffer`, so that error
+    // offsets stay relative to the whole stream.
+    discarded: usize,
+    state: State,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '+' | '-')
+}
+
+fn scalar_value(word: &str) -> Result<Value, String> {
+    match word {
+        "true" => Ok(proto!(Value { bool_value: true })),
+        "false" => Ok(proto!(Value { bool_value: false })),
+        "null" => Ok(proto!(Value { null_value: NullValue::default() })),
+        _ => match word.parse::<f64>() {
+            Ok(double_val) => Ok(proto!(Value { number_value: double_val })),
+            Err(_) => Err(format!("Failed to parse number: {}", word)),
+        },
+    }
+}
+
+impl Default for FcStreamParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FcStreamParser {
+    pub fn new() -> Self {
+        FcStreamParser { buffer: String::new(), pos: 0, discarded: 0, state: State::Call }
+    }
+
+    /// Appends a chunk of model output and returns the events it completed.
+    pub fn feed(&mut self, chunk: &str) -> Result<Vec<FcStreamEvent>, String> {
+        self.buffer.push_str(chunk);
+        self.drain(false)
+    }
+
+    /// Signals the end of the stream, flushing a trailing token if any.
+    pub fn finish(&mut self) -> Result<Vec<FcStreamEvent>, String> {
+        self.drain(true)
+    }
+
+    fn drain(&mut self, at_end: bool) -> Result<Vec<FcStreamEvent>, String> {
+        let mut events = Vec::new();
+        while let Some((token, end)) = self.next_token(at_end).map_err(|(message, offset)| {
+            format!("{} at offset {}", message, self.discarded + offset)
+        })? {
+            self.pos = end;
+            self.advance(token, &mut events)?;
+            if matches!(self.state, State::Call | State::AfterCall) {
+                self.buffer.drain(..self.pos);
+                self.discarded += self.pos;
+                self.pos = 0;
+            }
+        }
+        Ok(events)
+    }
+
+    // Returns the next complete token and the offset just past it, or None if
+    // more input is needed. Errors carry the offset in `buffer` they are at.
+    fn next_token(&self, at_end: bool) -> Result<Option<(Token, usize)>, (String, usize)> {
+        let rest = &self.buffer[self.pos..];
+        let trimmed = rest.trim_start();
+        let start = self.pos + (rest.len() - trimmed.len());
+        let Some(c) = trimmed.chars().next() else {
+            return Ok(None);
+        };
+        if matches!(c, '{' | '}' | '[' | ']' | ',' | ':') {
+            return Ok(Some((Token::Punct(c), start + 1)));
+        }
+        if c == '<' {
+            if trimmed.len() < ESCAPE.len() && ESCAPE.starts_with(trimmed) && !at_end {
+                return Ok(None);
+            }
+            if !trimmed.starts_with(ESCAPE) {
+                return Err(("Unexpected character '<'".to_string(), start));
+            }
+            return match trimmed[ESCAPE.len()..].find(ESCAPE) {
+                Some(i) => {
+                    let content = trimmed[ESCAPE.len()..ESCAPE.len() + i].to_string();
+                    Ok(Some((Token::Str(content), start + 2 * ESCAPE.len() + i)))
+                }
+                None if at_end => Err(("Unterminated string".to_string(), start)),
+                None => Ok(None),
+            };
+        }
+        if is_word_char(c) {
+            let len = trimmed.find(|c: char| !is_word_char(c)).unwrap_or(trimmed.len());
+            if len == trimmed.len() && !at_end {
+                return Ok(None);
+            }
+            return Ok(Some((Token::Word(trimmed[..len].to_string()), start + len)));
+        }
+        Err((format!("Unexpected character '{}'", c), start))
+    }
+
+    fn advance(&mut self, token: Token, events: &mut Vec<FcStreamEvent>) -> Result<(), String> {
+        let state = std::mem::replace(&mut self.state, State::Call);
+        self.state = match (state, token) {
+            (State::Call | State::AfterCall, Token::Word(w)) if w == "call" => State::CallColon,
+            (State::AfterCall, Token::Punct(',')) => State::Call,
+            (State::CallColon, Token::Punct(':')) => State::Name,
+            (State::Name, Token::Word(name)) => {
+                events.push(FcStreamEvent::FunctionStart(name));
+                State::ArgsOpen
+            }
+            (State::ArgsOpen, Token::Punct('{')) => State::Key,
+            (State::Key, Token::Punct('}')) => {
+                events.push(FcStreamEvent::FunctionEnd);
+                State::AfterCall
+            }
+            (State::Key, Token::Word(key)) => {
+                events.push(FcStreamEvent::KeySeen(key.clone()));
+                State::KeyColon(key)
+            }
+            (State::KeyColon(key), Token::Punct(':')) => State::Value(key),
+            (State::Value(key), Token::Str(s)) => {
+                events.push(FcStreamEvent::ScalarValue(key, proto!(Value { string_value: s })));
+                State::AfterValue
+            }
+            (State::Value(key), Token::Word(w)) => {
+                let value = scalar_value(&w)
+                    .map_err(|e| format!("Error parsing value for key '{}': {}", key, e))?;
+                events.push(FcStreamEvent::ScalarValue(key, value));
+                State::AfterValue
+            }
+            (State::Value(_), Token::Punct('{' | '[')) => State::Nested(1),
+            (State::Nested(depth), Token::Punct('{' | '[')) => State::Nested(depth + 1),
+            (State::Nested(1), Token::Punct('}' | ']')) => State::AfterValue,
+            (State::Nested(depth), Token::Punct('}' | ']')) => State::Nested(depth - 1),
+            (State::Nested(depth), _) => State::Nested(depth),
+            (State::AfterValue, Token::Punct(',')) => State::Key,
+            (State::AfterValue, Token::Punct('}')) => {
+                events.push(FcStreamEvent::FunctionEnd);
+                State::AfterCall
+            }
+            (state, token) => {
+                return Err(format!("Unexpected {:?} while in state {:?}", token, state));
+            }
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(events: &[FcStreamEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| match event {
+                FcStreamEvent::FunctionStart(name) => format!("start {}", name),
+                FcStreamEvent::KeySeen(key) => format!("key {}", key),
+                FcStreamEvent::ScalarValue(key, value) => format!("value {} {:?}", key, value),
+                FcStreamEvent::FunctionEnd => "end".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn emits_events_for_chunked_call() {
+        let mut parser = FcStreamParser::new();
+        let mut events = Vec::new();
+
+        events.extend(parser.feed("call:f{a").unwrap());
+        assert_eq!(describe(&events), vec!["start f"]);
+
+        for chunk in [":1,b:<esc", "ape>x<esc", "ape>", "}"] {
+            events.extend(parser.feed(chunk).unwrap());
+        }
+        events.extend(parser.finish().unwrap());
+
+        assert_eq!(
+            describe(&events),
+            vec![
+                "start f".to_string(),
+                "key a".to_string(),
+                format!("value a {:?}", proto!(Value { number_value: 1.0 })),
+                "key b".to_string(),
+                format!("value b {:?}", proto!(Value { string_value: "x" })),
+                "end".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_one_comma_between_calls() {
+        let mut parser = FcStreamParser::new();
+        let mut events = Vec::new();
+        for chunk in ["call:a{}", " ,", " call:b{x:1}", ",call:c{}"] {
+            events.extend(parser.feed(chunk).unwrap());
+        }
+        events.extend(parser.finish().unwrap());
+        let ends = describe(&events).into_iter().filter(|event| event == "end").count();
+        assert_eq!(ends, 3);
+
+        let mut parser = FcStreamParser::new();
+        assert!(parser.feed(", call:a{}").is_err());
+        let mut parser = FcStreamParser::new();
+        assert!(parser.feed("call:a{},, call:b{}").is_err());
+    }
+
+    #[test]
+    fn skips_nested_values() {
+        let mut parser = FcStreamParser::new();
+        let events = parser.feed("call:f{a:{b:[1,{c:2}]},d:true}").unwrap();
+        let described = describe(&events);
+        assert_eq!(described.len(), 5);
+        assert_eq!(described[1], "key a");
+        assert_eq!(described[2], "key d");
+        assert_eq!(described[4], "end");
+    }
+
+    #[test]
+    fn buffers_only_the_call_in_progress() {
+        let calls: Vec<String> = (0..200)
+            .map(|i| format!("call:f{{n:{},s:<escape>{}<escape>}} ", i, "x".repeat(i % 17)))
+            .collect();
+        let longest = calls.iter().map(String::len).max().unwrap();
+        let mut parser = FcStreamParser::new();
+        let mut ends = 0;
+        for call in &calls {
+            for chunk in call.as_bytes().chunks(3) {
+                let events = parser.feed(std::str::from_utf8(chunk).unwrap()).unwrap();
+                ends += events.iter().filter(|e| matches!(e, FcStreamEvent::FunctionEnd)).count();
+                assert!(parser.buffer.len() <= longest);
+            }
+        }
+        parser.finish().unwrap();
+        assert_eq!(ends, calls.len());
+
+        let err = parser.feed("call:f{a:%}").err().unwrap();
+        let offset = calls.iter().map(String::len).sum::<usize>() + "call:f{a:".len();
+        assert_eq!(err, format!("Unexpected character '%' at offset {}", offset));
+    }
+}
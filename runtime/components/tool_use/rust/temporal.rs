@@ -0,0 +1,163 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of the ISO-8601 DURATION and TIMESTAMP tokens to seconds. The
+//! lexer has already checked their shape; these functions check the values.
+
+// Whether `text` is one or more runs of digits, each followed by one of
+// `units`.
+fn is_unit_parts(text: &str, units: &str) -> bool {
+    let mut digits = 0;
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits += 1;
+        } else if digits > 0 && units.contains(c) {
+            digits = 0;
+        } else {
+            return false;
+        }
+    }
+    digits == 0
+}
+
+/// Whether `word`, an ID token, has the shape of a duration such as `P1D` or
+/// `PT1H30M`. The lexer leaves these to the parser, so that they stay usable
+/// as keys and names.
+pub(crate) fn is_duration(word: &str) -> bool {
+    let Some(rest) = word.strip_prefix('P') else {
+        return false;
+    };
+    match rest.split_once('T') {
+        Some((date, time)) => {
+            is_unit_parts(date, "YMWD") && !time.is_empty() && is_unit_parts(time, "HMS")
+        }
+        None => !rest.is_empty() && is_unit_parts(rest, "YMWD"),
+    }
+}
+
+/// Returns the length of a duration such as `P1DT2H30M` in seconds. Years and
+/// months have no fixed length and are rejected.
+pub(crate) fn duration_seconds(text: &str) -> Result<f64, String> {
+    let mut seconds = 0.0;
+    let mut in_time = false;
+    let mut number = String::new();
+    for c in text.chars().skip(1) {
+        match c {
+            'T' => in_time = true,
+            '0'..='9' | '.' => number.push(c),
+            _ => {
+                let unit = match (c, in_time) {
+                    ('W', false) => 604800.0,
+                    ('D', false) => 86400.0,
+                    ('H', true) => 3600.0,
+                    ('M', true) => 60.0,
+                    ('S', true) => 1.0,
+                    _ => return Err(format!("Duration {} has no fixed length in seconds", text)),
+                };
+                let n: f64 = number.parse().map_err(|_| format!("Invalid duration: {}", text))?;
+                seconds += n * unit;
+                number.clear();
+            }
+        }
+    }
+    Ok(seconds)
+}
+
+// Days from 1970-01-01 to the given proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns a timestamp such as `2025-01-02T03:04:05Z` as seconds since the
+/// Unix epoch.
+pub(crate) fn timestamp_seconds(text: &str) -> Result<f64, String> {
+    let invalid = || format!("Invalid timestamp: {}", text);
+    let field = |range: std::ops::Range<usize>| -> i64 { text[range].parse().unwrap_or(-1) };
+    let (year, month, day) = (field(0..4), field(5..7), field(8..10));
+    let (hour, minute, second) = (field(11..13), field(14..16), field(17..19));
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+
+    let rest = &text[19..];
+    let zone_start = rest.find(['Z', '+', '-']).ok_or_else(invalid)?;
+    let fraction: f64 = if zone_start > 0 {
+        format!("0{}", &rest[..zone_start]).parse().unwrap_or(0.0)
+    } else {
+        0.0
+    };
+    let zone = &rest[zone_start..];
+    let offset = if zone == "Z" {
+        0
+    } else {
+        let (hours, minutes) = (zone[1..3].parse::<i64>(), zone[4..6].parse::<i64>());
+        match (hours, minutes) {
+            (Ok(hours), Ok(minutes)) if hours <= 23 && minutes <= 59 => {
+                let offset = hours * 3600 + minutes * 60;
+                if zone.starts_with('-') {
+                    -offset
+                } else {
+                    offset
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Ok(seconds as f64 + fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_durations_and_timestamps_to_seconds() {
+        assert_eq!(duration_seconds("PT1H30M"), Ok(5400.0));
+        assert_eq!(duration_seconds("P1DT0.5S"), Ok(86400.5));
+        assert!(duration_seconds("P1Y").is_err());
+        for word in ["P1D", "PT1H30M", "P2WT5S", "P1Y2M"] {
+            assert!(is_duration(word), "{}", word);
+        }
+        for word in ["P", "PT", "P1DT", "PD", "P1", "P1H", "PT1D", "Page"] {
+            assert!(!is_duration(word), "{}", word);
+        }
+
+        assert_eq!(timestamp_seconds("1970-01-01T00:00:00Z"), Ok(0.0));
+        assert_eq!(timestamp_seconds("2025-01-02T03:04:05Z"), Ok(1735787045.0));
+        assert_eq!(timestamp_seconds("2025-01-02T05:04:05.25+02:00"), Ok(1735787045.25));
+        assert!(timestamp_seconds("2025-02-30T00:00:00Z").is_err());
+    }
+}
@@ -0,0 +1,844 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers operating on parsed ToolCall protos.
+
+use crate::value_utils::{rewrite_tool_calls, rewrite_value, value_eq, Rewrite};
+use crate::DuplicateNamePolicy;
+use protobuf::prelude::*;
+use protobuf::proto;
+use protobuf::{ProtoStr, ProtoString, RepeatedView};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::Infallible;
+use tool_call_rust_proto::value::KindOneof;
+use tool_call_rust_proto::{
+    Struct, StructView, ToolCall, ToolCallView, ToolCalls, Value, ValueView,
+};
+
+/// A tool call lowered into plain Rust types for an executor.
+#[derive(Clone, Debug)]
+pub struct PlannedCall {
+    pub name: String,
+    /// Top-level arguments by key. Nested objects and lists stay as Values.
+    pub args: BTreeMap<String, Value>,
+    /// Names of the `**$name` references spread into the arguments, in order,
+    /// for the executor to resolve and merge under `args`.
+    pub spread_refs: Vec<String>,
+}
+
+fn flatten_struct(prefix: &str, s: StructView, out: &mut BTreeMap<String, Value>) {
+    for field in s.fields() {
+        let path = if prefix.is_empty() {
+            field.name().to_string()
+        } else {
+            format!("{}.{}", prefix, field.name())
+        };
+        flatten_value(path, field.value(), out);
+    }
+}
+
+fn flatten_value(path: String, value: ValueView, out: &mut BTreeMap<String, Value>) {
+    match value.kind() {
+        KindOneof::StructValue(s) if !s.fields().is_empty() => flatten_struct(&path, s, out),
+        KindOneof::ListValue(l) if !l.values().is_empty() => {
+            for (index, element) in l.values().iter().enumerate() {
+                flatten_value(format!("{}[{}]", path, index), element, out);
+            }
+        }
+        _ => {
+            out.insert(path, value.to_owned());
+        }
+    }
+}
+
+/// Flattens the arguments of `tool_call` into a map from paths such as
+/// `user.name` or `user.roles[0]` to leaf values. Empty structs and lists are
+/// kept as leaves so that they are not lost.
+pub fn flatten_args(tool_call: &ToolCall) -> BTreeMap<String, Value> {
+    let mut out = BTreeMap::new();
+    flatten_struct("", tool_call.arguments(), &mut out);
+    out
+}
+
+/// Where the value of an argument came from, for audit logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provenance {
+    /// Written by the model and kept as it was.
+    FromModel,
+    /// Written by the model, then changed by a pass such as
+    /// [`coerce_numbers_to_int_where_exact`](crate::coerce_numbers_to_int_where_exact).
+    Coerced,
+    /// Left out by the model and filled in, as by
+    /// [`ToolSchema::apply_defaults`](crate::ToolSchema::apply_defaults).
+    DefaultInjected,
+}
+
+/// The provenance of each argument of a call, keyed by the paths of
+/// [`flatten_args`].
+pub type ArgProvenance = BTreeMap<String, Provenance>;
+
+/// Starts the provenance of `tool_call` as it came from the parser, with
+/// every argument [`Provenance::FromModel`].
+pub fn arg_provenance(tool_call: &ToolCall) -> ArgProvenance {
+    flatten_args(tool_call).into_keys().map(|path| (path, Provenance::FromModel)).collect()
+}
+
+/// Updates `provenance` for a pass that turned `before` into `after`: every
+/// argument the pass added or changed is attributed to `pass`, the others
+/// keep what they had, and arguments the pass removed are dropped.
+pub fn record_provenance(
+    provenance: &mut ArgProvenance,
+    before: &ToolCall,
+    after: &ToolCall,
+    pass: Provenance,
+) {
+    let before = flatten_args(before);
+    let after = flatten_args(after);
+    provenance.retain(|path, _| after.contains_key(path));
+    for (path, value) in after {
+        if before.get(&path).is_some_and(|old| value_eq(old, &value)) {
+            provenance.entry(path).or_insert(Provenance::FromModel);
+        } else {
+            provenance.insert(path, pass);
+        }
+    }
+}
+
+/// Gathers every string value in the arguments of `tool_calls`, at any
+/// depth, with its path prefixed by the function name, such as
+/// `send.to[0].name`. Calls come in order and each call's strings in path
+/// order, as in [`flatten_args`].
+pub fn collect_strings(tool_calls: &ToolCalls) -> Vec<(String, String)> {
+    let mut strings = Vec::new();
+    for tool_call in tool_calls.tool_calls() {
+        let mut flat = BTreeMap::new();
+        flatten_struct(&tool_call.name().to_string(), tool_call.arguments(), &mut flat);
+        strings.extend(flat.into_iter().filter_map(|(path, value)| match value.kind() {
+            KindOneof::StringValue(s) => Some((path, s.to_string())),
+            _ => None,
+        }));
+    }
+    strings
+}
+
+// The name of the proto field holding the value, such as `number_value`.
+fn kind_name(value: ValueView) -> &'static str {
+    match value.kind() {
+        KindOneof::NullValue(_) => "null_value",
+        KindOneof::NumberValue(_) => "number_value",
+        KindOneof::StringValue(_) => "string_value",
+        KindOneof::BoolValue(_) => "bool_value",
+        KindOneof::StructValue(_) => "struct_value",
+        KindOneof::ListValue(_) => "list_value",
+        KindOneof::IntValue(_) => "int_value",
+        KindOneof::ReferenceValue(_) => "reference_value",
+        KindOneof::BytesValue(_) => "bytes_value",
+        KindOneof::CallValue(_) => "call_value",
+        _ => "not_set",
+    }
+}
+
+fn record_kinds(path: String, value: ValueView, out: &mut BTreeMap<String, BTreeSet<String>>) {
+    match value.kind() {
+        KindOneof::StructValue(s) => {
+            for field in s.fields() {
+                record_kinds(format!("{}.{}", path, field.name()), field.value(), out);
+            }
+        }
+        KindOneof::ListValue(l) => {
+            for element in l.values() {
+                record_kinds(format!("{}[]", path), element, out);
+            }
+        }
+        _ => {}
+    }
+    out.entry(path).or_default().insert(kind_name(value).to_string());
+}
+
+/// Records which Value kinds, named by their proto field such as
+/// `number_value`, each argument path took across all of `tool_calls`.
+/// Paths do not include the function name, nested objects and lists are
+/// recorded along with what they hold, and every element of a list shares
+/// one path, such as `tags[]`.
+pub fn value_kinds(tool_calls: &ToolCalls) -> BTreeMap<String, BTreeSet<String>> {
+    let mut kinds = BTreeMap::new();
+    for tool_call in tool_calls.tool_calls() {
+        for field in tool_call.arguments().fields() {
+            record_kinds(field.name().to_string(), field.value(), &mut kinds);
+        }
+    }
+    kinds
+}
+
+/// Finds the top-level argument `key` in each call of `tool_calls`, returning
+/// the function name and value of every call that has it, in order. A call
+/// with the key more than once contributes its first value only.
+pub fn find_arg<'a>(tool_calls: &'a ToolCalls, key: &str) -> Vec<(&'a ProtoStr, ValueView<'a>)> {
+    tool_calls
+        .tool_calls()
+        .iter()
+        .filter_map(|tool_call| {
+            let field = tool_call.arguments().fields().iter().find(|field| field.name() == key)?;
+            Some((tool_call.name(), field.value()))
+        })
+        .collect()
+}
+
+/// Splits `tool_call` into its function name and arguments, for executors
+/// that take the two apart. A call without arguments gets an empty Struct.
+pub fn into_name_and_args(tool_call: ToolCall) -> (String, Struct) {
+    (tool_call.name().to_string(), tool_call.arguments().to_owned())
+}
+
+/// Like [`into_name_and_args`], borrowing from `tool_call` instead of
+/// copying.
+pub fn name_and_args(tool_call: &ToolCall) -> (&ProtoStr, StructView<'_>) {
+    (tool_call.name(), tool_call.arguments())
+}
+
+/// Lowers every call in `tool_calls` into a [`PlannedCall`], in order.
+pub fn to_execution_plan(tool_calls: &ToolCalls) -> Vec<PlannedCall> {
+    tool_calls
+        .tool_calls()
+        .iter()
+        .map(|tool_call| PlannedCall {
+            name: tool_call.name().to_string(),
+            args: tool_call
+                .arguments()
+                .fields()
+                .iter()
+                .map(|field| (field.name().to_string(), field.value().to_owned()))
+                .collect(),
+            spread_refs: tool_call.spread_refs().iter().map(|name| name.to_string()).collect(),
+        })
+        .collect()
+}
+
+/// Consumes `tool_calls` into a map from function name to call, resolving
+/// repeated names according to `policy`.
+pub fn tool_calls_by_name(
+    tool_calls: ToolCalls,
+    policy: DuplicateNamePolicy,
+) -> Result<HashMap<String, ToolCall>, String> {
+    let mut by_name = HashMap::new();
+    for tool_call in tool_calls.tool_calls() {
+        let name = tool_call.name().to_string();
+        if by_name.insert(name.clone(), tool_call.to_owned()).is_some()
+            && policy == DuplicateNamePolicy::Error
+        {
+            return Err(format!("Function {} is called more than once", name));
+        }
+    }
+    Ok(by_name)
+}
+
+// Gathers the names of the references and spreads in `value`, including
+// those in the arguments of nested calls.
+fn collect_references(value: ValueView, out: &mut Vec<String>) {
+    match value.kind() {
+        KindOneof::ReferenceValue(name) => out.push(name.to_string()),
+        KindOneof::StructValue(s) => {
+            s.fields().iter().for_each(|field| collect_references(field.value(), out))
+        }
+        KindOneof::ListValue(l) => l.values().iter().for_each(|e| collect_references(e, out)),
+        KindOneof::CallValue(call) => collect_call_references(call, out),
+        _ => {}
+    }
+}
+
+fn collect_call_references(tool_call: ToolCallView, out: &mut Vec<String>) {
+    out.extend(tool_call.spread_refs().iter().map(|name| name.to_string()));
+    for field in tool_call.arguments().fields() {
+        collect_references(field.value(), out);
+    }
+}
+
+/// Checks that every `$name` reference and `**$name` spread in
+/// `tool_calls` names an earlier call, whose result is what it stands for.
+/// Returns one message per reference that can never resolve, in order.
+pub fn check_references(tool_calls: &ToolCalls) -> Result<(), Vec<String>> {
+    let mut defined = HashSet::new();
+    let mut dangling = Vec::new();
+    for tool_call in tool_calls.tool_calls() {
+        let mut references = Vec::new();
+        collect_call_references(tool_call, &mut references);
+        for reference in references {
+            if !defined.contains(&reference) {
+                dangling.push(format!(
+                    "Reference ${} in {} names no earlier call",
+                    reference,
+                    tool_call.name()
+                ));
+            }
+        }
+        defined.insert(tool_call.name().to_string());
+    }
+    if dangling.is_empty() {
+        Ok(())
+    } else {
+        Err(dangling)
+    }
+}
+
+// A short name for the kind of `value`, with objects and lists collapsed.
+fn summary_kind(value: ValueView) -> &'static str {
+    match value.kind() {
+        KindOneof::NullValue(_) => "null",
+        KindOneof::NumberValue(_) => "number",
+        KindOneof::IntValue(_) => "int",
+        KindOneof::StringValue(_) => "str",
+        KindOneof::BoolValue(_) => "bool",
+        KindOneof::ReferenceValue(_) => "ref",
+        KindOneof::BytesValue(_) => "bytes",
+        KindOneof::CallValue(_) => "call",
+        KindOneof::StructValue(_) => "{...}",
+        KindOneof::ListValue(_) => "[...]",
+        _ => "unset",
+    }
+}
+
+/// Describes `tool_calls` one line per call, such as
+/// `search(q: str, limit: int)`, with the kind of each argument in place of
+/// its value so that the summary leaks no data. Objects and lists collapse
+/// to `{...}` and `[...]`, and spreads are listed first as `**$name`.
+pub fn summary(tool_calls: &ToolCalls) -> String {
+    let lines: Vec<String> = tool_calls
+        .tool_calls()
+        .iter()
+        .map(|tool_call| {
+            let spreads = tool_call.spread_refs().iter().map(|name| format!("**${}", name));
+            let arguments = tool_call
+                .arguments()
+                .fields()
+                .iter()
+                .map(|field| format!("{}: {}", field.name(), summary_kind(field.value())));
+            format!(
+                "{}({})",
+                tool_call.name(),
+                spreads.chain(arguments).collect::<Vec<_>>().join(", ")
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// How the calls in a ToolCalls line up with a registry of tools.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsageReport {
+    /// Number of calls to each invoked name, registered or not.
+    pub calls: BTreeMap<String, usize>,
+    /// Invoked names that are not in the registry.
+    pub unknown: BTreeSet<String>,
+    /// Registered tools that were not invoked.
+    pub unused: BTreeSet<String>,
+}
+
+/// Tallies the calls in `tool_calls` against the names in `registry`.
+pub fn usage_report(tool_calls: &ToolCalls, registry: &HashSet<String>) -> UsageReport {
+    let mut report = UsageReport::default();
+    for tool_call in tool_calls.tool_calls() {
+        let name = tool_call.name().to_string();
+        if !registry.contains(&name) {
+            report.unknown.insert(name.clone());
+        }
+        *report.calls.entry(name).or_default() += 1;
+    }
+    report.unused =
+        registry.iter().filter(|name| !report.calls.contains_key(*name)).cloned().collect();
+    report
+}
+
+/// Replaces redacted scalar values.
+pub const REDACTED: &str = "***";
+
+// Redacts the values of sensitive keys and, once `all` is set for what
+// such a key holds, every scalar value.
+struct Redact<'a> {
+    sensitive_keys: &'a HashSet<String>,
+    all: bool,
+}
+
+impl Rewrite for Redact<'_> {
+    type Error = Infallible;
+
+    fn before(
+        &mut self,
+        path: &str,
+        key: Option<&str>,
+        value: ValueView,
+    ) -> Result<Option<Value>, Infallible> {
+        if self.all {
+            return Ok(match value.kind() {
+                KindOneof::StructValue(_) | KindOneof::ListValue(_) | KindOneof::CallValue(_) => {
+                    None
+                }
+                _ => Some(proto!(Value { string_value: REDACTED })),
+            });
+        }
+        if !key.is_some_and(|key| self.sensitive_keys.contains(key)) {
+            return Ok(None);
+        }
+        // The elements of a list, the fields of an object and the arguments
+        // of a nested call all belong to the key holding them.
+        let mut all = Redact { sensitive_keys: self.sensitive_keys, all: true };
+        rewrite_value(value, path, key, &mut all).map(Some)
+    }
+}
+
+/// Replaces the scalar values of arguments named in `sensitive_keys`,
+/// booleans, nulls and reference names included, with [`REDACTED`], at any
+/// depth and in nested calls too, so that the calls can be logged.
+/// Everything held by a sensitive key, such as the fields of an object, is
+/// redacted with it. Keys are matched exactly; other values are left
+/// untouched.
+pub fn redact(tool_calls: &mut ToolCalls, sensitive_keys: &HashSet<String>) {
+    rewrite_tool_calls(tool_calls, &mut Redact { sensitive_keys, all: false })
+        .unwrap_or_else(|never| match never {})
+}
+
+/// Renames each call in `tool_calls` whose name is a key of `mapping` to the
+/// name it maps to, such as a logical tool name to the implementation that
+/// serves it. Unmapped names, and calls nested in the arguments, are left
+/// unchanged.
+pub fn remap_names(tool_calls: &mut ToolCalls, mapping: &HashMap<String, String>) {
+    let remapped: Vec<ToolCall> = tool_calls
+        .tool_calls()
+        .iter()
+        .map(|tool_call| {
+            let mut tool_call = tool_call.to_owned();
+            if let Some(name) = mapping.get(&tool_call.name().to_string()) {
+                tool_call.set_name(name.as_str());
+            }
+            tool_call
+        })
+        .collect();
+    tool_calls.set_tool_calls(remapped.into_iter());
+}
+
+// Replaces strings and bytes longer than the limit it holds.
+struct Truncate(usize);
+
+impl Rewrite for Truncate {
+    type Error = Infallible;
+
+    fn before(
+        &mut self,
+        _path: &str,
+        _key: Option<&str>,
+        value: ValueView,
+    ) -> Result<Option<Value>, Infallible> {
+        let marker =
+            |len: usize| proto!(Value { string_value: format!("<truncated {} bytes>", len) });
+        Ok(match value.kind() {
+            KindOneof::StringValue(s) if s.len() > self.0 => Some(marker(s.len())),
+            KindOneof::BytesValue(bytes) if bytes.len() > self.0 => Some(marker(bytes.len())),
+            _ => None,
+        })
+    }
+}
+
+/// Replaces every string and bytes value longer than `max_value_bytes`, at
+/// any depth and in nested calls too, with the string `<truncated N bytes>`
+/// giving its original length, so that the calls fit a storage budget
+/// without losing any of them.
+pub fn truncate_large_values(tool_calls: &mut ToolCalls, max_value_bytes: usize) {
+    rewrite_tool_calls(tool_calls, &mut Truncate(max_value_bytes))
+        .unwrap_or_else(|never| match never {})
+}
+
+/// Serializes each call as its own ToolCall proto, in order, for
+/// dispatchers that send every call to a different worker.
+pub fn serialize_each(tool_calls: &ToolCalls) -> Result<Vec<Vec<u8>>, String> {
+    tool_calls
+        .tool_calls()
+        .iter()
+        .map(|tool_call| tool_call.to_owned().serialize().map_err(|e| e.to_string()))
+        .collect()
+}
+
+// Unions the arguments of `other` into `target`, replacing the values of
+// keys both have, and takes the confidence of `other` if it has one.
+fn merge_call(target: &mut ToolCall, other: ToolCallView) {
+    if other.has_arguments() {
+        let mut arguments = target.arguments().to_owned();
+        for field in other.arguments().fields() {
+            match arguments.fields().iter().position(|existing| existing.name() == field.name()) {
+                Some(index) => arguments.fields_mut().set(index, field),
+                None => arguments.fields_mut().push(field),
+            }
+        }
+        target.set_arguments(arguments);
+    }
+    let strings = |a: RepeatedView<ProtoString>, b: RepeatedView<ProtoString>| -> Vec<String> {
+        a.iter().chain(b.iter()).map(|s| s.to_string()).collect()
+    };
+    let spread_refs = strings(target.spread_refs(), other.spread_refs());
+    target.set_spread_refs(spread_refs.into_iter());
+    let comments = strings(target.comments(), other.comments());
+    target.set_comments(comments.into_iter());
+    if other.has_confidence() {
+        target.set_confidence(other.confidence());
+    }
+}
+
+/// Merges each run of adjacent calls to the same function with the same
+/// dispatch verb into one call whose arguments are the union of theirs,
+/// later values winning.
+pub(crate) fn merge_consecutive_calls(tool_calls: &mut ToolCalls) {
+    let mut merged: Vec<ToolCall> = Vec::new();
+    for tool_call in tool_calls.tool_calls() {
+        match merged.last_mut() {
+            Some(last)
+                if last.name() == tool_call.name()
+                    && last.dispatch_verb() == tool_call.dispatch_verb() =>
+            {
+                merge_call(last, tool_call)
+            }
+            _ => merged.push(tool_call.to_owned()),
+        }
+    }
+    tool_calls.set_tool_calls(merged.into_iter());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fc_expression, parse_fc_expression_with_options, value_eq, ParseOptions};
+
+    fn parse(text: &str) -> ToolCalls {
+        let result = parse_fc_expression(text);
+        assert!(result.is_ok, "{}", result.error);
+        ToolCalls::parse(&result.serialized_tool_calls).unwrap()
+    }
+
+    #[test]
+    fn flattens_nested_struct_and_list() {
+        let tool_calls = parse(
+            "call:f{user:{name:<escape>a<escape>,roles:[<escape>admin<escape>,<escape>dev<escape>]},n:1}",
+        );
+
+        let flat = flatten_args(&tool_calls.tool_calls().get(0).unwrap().to_owned());
+        assert_eq!(
+            flat.keys().collect::<Vec<_>>(),
+            vec!["n", "user.name", "user.roles[0]", "user.roles[1]"]
+        );
+        assert_eq!(flat["user.name"].string_value().to_string(), "a");
+        assert_eq!(flat["user.roles[0]"].string_value().to_string(), "admin");
+        assert_eq!(flat["n"].number_value(), 1.0);
+    }
+
+    #[test]
+    fn attributes_each_argument_to_the_pass_that_set_it() {
+        let schema = crate::ToolSchema::from_json_schema(
+            r#"{
+                "properties": {
+                    "days": {"type": "integer"},
+                    "unit": {"default": "celsius"},
+                    "opts": {"properties": {"precision": {"default": 1}}}
+                }
+            }"#,
+        )
+        .unwrap();
+        let mut tool_calls =
+            parse("call:weather{city:<escape>Paris<escape>,days:3,opts:{metric:true}}");
+        let call = |tool_calls: &ToolCalls| tool_calls.tool_calls().get(0).unwrap().to_owned();
+
+        let mut provenance = arg_provenance(&call(&tool_calls));
+        let before = call(&tool_calls);
+        crate::coerce_numbers_to_int_where_exact(&mut tool_calls);
+        record_provenance(&mut provenance, &before, &call(&tool_calls), Provenance::Coerced);
+        let mut tool_call = call(&tool_calls);
+        let before = tool_call.clone();
+        assert_eq!(schema.apply_defaults(&mut tool_call), vec!["opts.precision", "unit"]);
+        record_provenance(&mut provenance, &before, &tool_call, Provenance::DefaultInjected);
+
+        assert_eq!(schema.validate(&tool_call), Ok(()));
+        assert_eq!(
+            provenance.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("city".to_string(), Provenance::FromModel),
+                ("days".to_string(), Provenance::Coerced),
+                ("opts.metric".to_string(), Provenance::FromModel),
+                ("opts.precision".to_string(), Provenance::DefaultInjected),
+                ("unit".to_string(), Provenance::DefaultInjected),
+            ]
+        );
+    }
+
+    #[test]
+    fn collects_nested_strings_with_paths() {
+        let mut tool_calls = parse(
+            "call:send{to:[{name:<escape>ann<escape>,id:1},<escape>bob<escape>],body:{text:<escape>hi<escape>,urgent:true}}",
+        );
+        tool_calls.tool_calls_mut().push(
+            parse("call:log{msg:<escape>sent<escape>,n:2}").tool_calls().get(0).unwrap().to_owned(),
+        );
+
+        let strings = collect_strings(&tool_calls);
+        let expected = [
+            ("send.body.text", "hi"),
+            ("send.to[0].name", "ann"),
+            ("send.to[1]", "bob"),
+            ("log.msg", "sent"),
+        ];
+        assert_eq!(strings.len(), expected.len());
+        for ((path, value), (expected_path, expected_value)) in strings.iter().zip(expected) {
+            assert_eq!((path.as_str(), value.as_str()), (expected_path, expected_value));
+        }
+    }
+
+    #[test]
+    fn records_every_kind_an_argument_took() {
+        let tool_calls = parse(
+            "call:add{count:3,tags:[<escape>a<escape>,1]}, call:add{count:<escape>3<escape>}",
+        );
+        let kinds = value_kinds(&tool_calls);
+        let names = |path: &str| kinds[path].iter().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(kinds.len(), 3);
+        assert_eq!(names("count"), ["number_value", "string_value"]);
+        assert_eq!(names("tags"), ["list_value"]);
+        assert_eq!(names("tags[]"), ["number_value", "string_value"]);
+    }
+
+    #[test]
+    fn reports_references_to_no_earlier_call() {
+        let tool_calls = parse("call:step1{}, call:step2{x:$step1}");
+        assert_eq!(check_references(&tool_calls), Ok(()));
+
+        let tool_calls = parse("call:step2{x:$step1,l:[{y:$step2}],**$step3}, call:step3{}");
+        assert_eq!(
+            check_references(&tool_calls).unwrap_err(),
+            [
+                "Reference $step3 in step2 names no earlier call",
+                "Reference $step1 in step2 names no earlier call",
+                "Reference $step2 in step2 names no earlier call",
+            ]
+        );
+    }
+
+    #[test]
+    fn lowers_calls_into_execution_plan() {
+        let tool_calls = parse("call:a{x:1,opts:{deep:[true]},**$base}");
+        let mut both = tool_calls.clone();
+        both.tool_calls_mut().push(parse("call:b{}").tool_calls().get(0).unwrap().to_owned());
+
+        let plan = to_execution_plan(&both);
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan[0].name, "a");
+        assert_eq!(plan[0].args.keys().collect::<Vec<_>>(), vec!["opts", "x"]);
+        assert_eq!(plan[0].args["x"].number_value(), 1.0);
+        let opts = tool_calls.tool_calls().get(0).unwrap().arguments().fields().get(1).unwrap();
+        assert!(value_eq(&plan[0].args["opts"], &opts.value().to_owned()));
+        assert_eq!(plan[0].spread_refs, vec!["base"]);
+        assert_eq!(plan[1].name, "b");
+        assert!(plan[1].args.is_empty());
+        assert!(plan[1].spread_refs.is_empty());
+    }
+
+    #[test]
+    fn resolves_duplicate_names_by_policy() {
+        let mut tool_calls = parse("call:f{x:1}");
+        for text in ["call:g{}", "call:f{x:2}"] {
+            tool_calls.tool_calls_mut().push(parse(text).tool_calls().get(0).unwrap().to_owned());
+        }
+
+        let error = tool_calls_by_name(tool_calls.clone(), DuplicateNamePolicy::Error).unwrap_err();
+        assert_eq!(error, "Function f is called more than once");
+
+        let by_name = tool_calls_by_name(tool_calls, DuplicateNamePolicy::LastWins).unwrap();
+        assert_eq!(by_name.len(), 2);
+        let x = by_name["f"].arguments().fields().get(0).unwrap().value().number_value();
+        assert_eq!(x, 2.0);
+    }
+
+    #[test]
+    fn finds_an_argument_in_every_call_that_has_it() {
+        let tool_calls = parse(
+            "call:search{q:<escape>cats<escape>,limit:5},call:open{n:1},call:lookup{n:2,q:<escape>dogs<escape>}",
+        );
+        let found: Vec<(String, String)> = find_arg(&tool_calls, "q")
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.string_value().to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("search".to_string(), "cats".to_string()),
+                ("lookup".to_string(), "dogs".to_string())
+            ]
+        );
+        assert!(find_arg(&tool_calls, "missing").is_empty());
+    }
+
+    #[test]
+    fn splits_a_call_into_name_and_arguments() {
+        let tool_calls = parse("call:search{q:<escape>cats<escape>,limit:5}");
+        let tool_call = tool_calls.tool_calls().get(0).unwrap().to_owned();
+
+        let (name, args) = name_and_args(&tool_call);
+        assert_eq!(name, "search");
+        assert_eq!(args.fields().len(), 2);
+
+        let (name, args) = into_name_and_args(tool_call);
+        assert_eq!(name, "search");
+        let keys: Vec<String> = args.fields().iter().map(|f| f.name().to_string()).collect();
+        assert_eq!(keys, ["q", "limit"]);
+        assert_eq!(args.fields().get(1).unwrap().value().number_value(), 5.0);
+
+        let (name, args) =
+            into_name_and_args(parse("call:ping{}").tool_calls().get(0).unwrap().to_owned());
+        assert_eq!((name.as_str(), args.fields().len()), ("ping", 0));
+    }
+
+    #[test]
+    fn redacts_sensitive_values_at_any_depth() {
+        let mut tool_calls = parse(
+            "call:login{user:<escape>ana<escape>,auth:{password:<escape>hunter2<escape>,pin:1234,remember:true},token:[<escape>t1<escape>]}",
+        );
+        let sensitive: HashSet<String> =
+            ["password", "pin", "token", "remember"].into_iter().map(String::from).collect();
+        redact(&mut tool_calls, &sensitive);
+
+        let flat = flatten_args(&tool_calls.tool_calls().get(0).unwrap().to_owned());
+        assert_eq!(flat["user"].string_value().to_string(), "ana");
+        assert_eq!(flat["auth.password"].string_value().to_string(), REDACTED);
+        assert_eq!(flat["auth.pin"].string_value().to_string(), REDACTED);
+        assert_eq!(flat["auth.remember"].string_value().to_string(), REDACTED);
+        assert_eq!(flat["token[0]"].string_value().to_string(), REDACTED);
+    }
+
+    #[test]
+    fn redacts_everything_a_sensitive_key_holds() {
+        let options = ParseOptions { nested_calls: true, ..Default::default() };
+        let result = parse_fc_expression_with_options(
+            "call:login{auth:call:vault{key:<escape>k1<escape>,scope:<escape>all<escape>},secret:{user:<escape>ana<escape>,n:1,ok:true,gone:null},password:$vault}",
+            &options,
+        );
+        assert!(result.is_ok, "{}", result.error);
+        let mut tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+        let mut blob = tool_call_rust_proto::Field::new();
+        blob.set_name("blob");
+        blob.set_value(proto!(Value { bytes_value: b"hi".to_vec() }));
+        tool_calls.tool_calls_mut().get_mut(0).unwrap().arguments_mut().fields_mut().push(blob);
+        let sensitive: HashSet<String> =
+            ["key", "secret", "blob", "password"].into_iter().map(String::from).collect();
+        redact(&mut tool_calls, &sensitive);
+
+        let tool_call = tool_calls.tool_calls().get(0).unwrap().to_owned();
+        let flat = flatten_args(&tool_call);
+        assert_eq!(flat["secret.user"].string_value().to_string(), REDACTED);
+        assert_eq!(flat["secret.n"].string_value().to_string(), REDACTED);
+        assert_eq!(flat["secret.ok"].string_value().to_string(), REDACTED);
+        assert_eq!(flat["secret.gone"].string_value().to_string(), REDACTED);
+        assert_eq!(flat["password"].string_value().to_string(), REDACTED);
+        assert_eq!(flat["blob"].string_value().to_string(), REDACTED);
+        let vault = flatten_args(&flat["auth"].call_value().to_owned());
+        assert_eq!(vault["key"].string_value().to_string(), REDACTED);
+        assert_eq!(vault["scope"].string_value().to_string(), "all");
+    }
+
+    #[test]
+    fn remaps_mapped_names_only() {
+        let mut tool_calls = parse("call:search{q:<escape>x<escape>},call:open{n:1}");
+        let original_arguments: Vec<_> = tool_calls
+            .tool_calls()
+            .iter()
+            .map(|call| call.arguments().to_owned().serialize().unwrap())
+            .collect();
+        let mapping = HashMap::from([("search".to_string(), "web_search_v2".to_string())]);
+        remap_names(&mut tool_calls, &mapping);
+
+        let names: Vec<String> =
+            tool_calls.tool_calls().iter().map(|call| call.name().to_string()).collect();
+        assert_eq!(names, ["web_search_v2", "open"]);
+        let arguments: Vec<_> = tool_calls
+            .tool_calls()
+            .iter()
+            .map(|call| call.arguments().to_owned().serialize().unwrap())
+            .collect();
+        assert_eq!(arguments, original_arguments);
+    }
+
+    #[test]
+    fn truncates_large_values_and_keeps_small_ones() {
+        let long = "x".repeat(100);
+        let mut tool_calls = parse(&format!(
+            "call:upload{{name:<escape>a.txt<escape>,file:{{body:<escape>{}<escape>,size:100}},parts:[<escape>{}<escape>,<escape>ok<escape>]}}",
+            long, long
+        ));
+        truncate_large_values(&mut tool_calls, 16);
+
+        let flat = flatten_args(&tool_calls.tool_calls().get(0).unwrap().to_owned());
+        assert_eq!(flat["name"].string_value().to_string(), "a.txt");
+        assert_eq!(flat["file.body"].string_value().to_string(), "<truncated 100 bytes>");
+        assert_eq!(flat["file.size"].number_value(), 100.0);
+        assert_eq!(flat["parts[0]"].string_value().to_string(), "<truncated 100 bytes>");
+        assert_eq!(flat["parts[1]"].string_value().to_string(), "ok");
+    }
+
+    #[test]
+    fn summarizes_argument_kinds_without_values() {
+        let options = ParseOptions { integer_values: true, ..Default::default() };
+        let result = parse_fc_expression_with_options(
+            "call:search{q:<escape>secret plans<escape>,limit:10,ratio:0.5,filters:{safe:true},tags:[1]}, call:open{**$hit,id:$hit,raw:null}",
+            &options,
+        );
+        assert!(result.is_ok, "{}", result.error);
+        let tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+
+        let text = summary(&tool_calls);
+        assert_eq!(
+            text,
+            "search(q: str, limit: int, ratio: number, filters: {...}, tags: [...])\nopen(**$hit, id: ref, raw: null)"
+        );
+        assert!(!text.contains("secret") && !text.contains("10"));
+    }
+
+    #[test]
+    fn reports_usage_against_registry() {
+        let tool_calls = parse("call:search{}, call:rm{}, call:search{q:1}, call:open{}");
+        let registry: HashSet<String> =
+            ["search", "open", "close"].into_iter().map(String::from).collect();
+
+        let report = usage_report(&tool_calls, &registry);
+        assert_eq!(
+            report.calls,
+            BTreeMap::from([
+                ("open".to_string(), 1),
+                ("rm".to_string(), 1),
+                ("search".to_string(), 2)
+            ])
+        );
+        assert_eq!(report.unknown, BTreeSet::from(["rm".to_string()]));
+        assert_eq!(report.unused, BTreeSet::from(["close".to_string()]));
+    }
+
+    #[test]
+    fn serializes_each_call_on_its_own() {
+        let mut tool_calls = parse("call:a{x:1,opts:{deep:[true]}}");
+        tool_calls.tool_calls_mut().push(parse("call:b{}").tool_calls().get(0).unwrap().to_owned());
+
+        let blobs = serialize_each(&tool_calls).unwrap();
+        assert_eq!(blobs.len(), 2);
+        for (blob, tool_call) in blobs.iter().zip(tool_calls.tool_calls()) {
+            let parsed = ToolCall::parse(blob).unwrap();
+            assert_eq!(parsed.name(), tool_call.name());
+            let (a, b) = (parsed.arguments().to_owned(), tool_call.arguments().to_owned());
+            assert!(value_eq(
+                &proto!(Value { struct_value: a }),
+                &proto!(Value { struct_value: b })
+            ));
+        }
+    }
+}
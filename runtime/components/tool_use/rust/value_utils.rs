@@ -0,0 +1,708 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for comparing and rewriting Value protos.
+
+use crate::ValueTransformer;
+use protobuf::proto;
+use tool_call_rust_proto::value::{KindCase, KindOneof};
+use tool_call_rust_proto::{
+    Field, ListValue, Struct, StructView, ToolCall, ToolCallView, ToolCalls, Value, ValueView,
+};
+
+fn struct_view_eq(a: StructView, b: StructView) -> bool {
+    if a.fields().len() != b.fields().len() {
+        return false;
+    }
+    a.fields().iter().all(|a_field| {
+        b.fields()
+            .iter()
+            .find(|b_field| b_field.name() == a_field.name())
+            .is_some_and(|b_field| value_view_eq(a_field.value(), b_field.value()))
+    })
+}
+
+pub(crate) fn value_view_eq(a: ValueView, b: ValueView) -> bool {
+    match (a.kind(), b.kind()) {
+        (KindOneof::NullValue(_), KindOneof::NullValue(_)) => true,
+        (KindOneof::NumberValue(a), KindOneof::NumberValue(b)) => a == b,
+        (KindOneof::IntValue(a), KindOneof::IntValue(b)) => a == b,
+        (KindOneof::ReferenceValue(a), KindOneof::ReferenceValue(b)) => a == b,
+        (KindOneof::StringValue(a), KindOneof::StringValue(b)) => a == b,
+        (KindOneof::BoolValue(a), KindOneof::BoolValue(b)) => a == b,
+        (KindOneof::BytesValue(a), KindOneof::BytesValue(b)) => a == b,
+        (KindOneof::CallValue(a), KindOneof::CallValue(b)) => tool_call_view_eq(a, b),
+        (KindOneof::StructValue(a), KindOneof::StructValue(b)) => struct_view_eq(a, b),
+        (KindOneof::ListValue(a), KindOneof::ListValue(b)) => {
+            a.values().len() == b.values().len()
+                && a.values().iter().zip(b.values().iter()).all(|(a, b)| value_view_eq(a, b))
+        }
+        (KindOneof::not_set(_), KindOneof::not_set(_)) => true,
+        _ => false,
+    }
+}
+
+/// Deep equality of two values. Struct fields are compared by key regardless
+/// of their order; list elements are compared in order.
+pub fn value_eq(a: &Value, b: &Value) -> bool {
+    value_view_eq(a.as_view(), b.as_view())
+}
+
+// Compares every field of two calls, the arguments as value_eq does and the
+// repeated fields in order.
+fn tool_call_view_eq(a: ToolCallView, b: ToolCallView) -> bool {
+    a.name() == b.name()
+        && a.has_arguments() == b.has_arguments()
+        && struct_view_eq(a.arguments(), b.arguments())
+        && a.spread_refs().iter().eq(b.spread_refs().iter())
+        && a.comments().iter().eq(b.comments().iter())
+        && a.confidence_opt().into_option() == b.confidence_opt().into_option()
+        && a.dispatch_verb() == b.dispatch_verb()
+}
+
+/// Returns the indices of the calls that differ between `original` and
+/// `modified` in any field, comparing their arguments as [`value_eq`] does.
+/// An index present in only one of them counts as changed.
+pub fn changed_indices(original: &ToolCalls, modified: &ToolCalls) -> Vec<usize> {
+    let (a, b) = (original.tool_calls(), modified.tool_calls());
+    (0..a.len().max(b.len()))
+        .filter(|&i| match (a.get(i), b.get(i)) {
+            (Some(a), Some(b)) => !tool_call_view_eq(a, b),
+            _ => true,
+        })
+        .collect()
+}
+
+/// Sorts the fields of every Struct nested in `value` by key, so that equal
+/// values also serialize identically.
+pub fn normalize(value: &mut Value) {
+    match value.kind_case() {
+        KindCase::StructValue => {
+            let mut fields: Vec<Field> = value
+                .struct_value()
+                .fields()
+                .iter()
+                .map(|field| {
+                    let mut field = field.to_owned();
+                    let mut field_value = field.value().to_owned();
+                    normalize(&mut field_value);
+                    field.set_value(field_value);
+                    field
+                })
+                .collect();
+            fields.sort_by(|a, b| a.name().cmp(b.name()));
+            value.struct_value_mut().set_fields(fields.into_iter());
+        }
+        KindCase::ListValue => {
+            let values: Vec<Value> = value
+                .list_value()
+                .values()
+                .iter()
+                .map(|element| {
+                    let mut element = element.to_owned();
+                    normalize(&mut element);
+                    element
+                })
+                .collect();
+            value.list_value_mut().set_values(values.into_iter());
+        }
+        _ => {}
+    }
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// A rewrite of argument values, run over a whole call by [`rewrite_call`].
+/// The walk builds each rewritten Struct and ListValue once from the views
+/// it reads, rather than copying a subtree and then replacing what it holds.
+pub(crate) trait Rewrite {
+    type Error;
+
+    /// Whether the walk goes into the arguments of calls nested as values.
+    const INTO_CALLS: bool = true;
+
+    /// Returns a value to store in place of `value`, found at `path` as the
+    /// field `key` or, with `key` None, as a list element, and not walked
+    /// into. None walks into it.
+    fn before(
+        &mut self,
+        _path: &str,
+        _key: Option<&str>,
+        _value: ValueView,
+    ) -> Result<Option<Value>, Self::Error> {
+        Ok(None)
+    }
+
+    /// Returns the value to store once everything `value` holds has been
+    /// rewritten.
+    fn after(&mut self, _path: &str, value: Value) -> Result<Value, Self::Error> {
+        Ok(value)
+    }
+}
+
+pub(crate) fn rewrite_struct<R: Rewrite>(
+    s: StructView,
+    path: &str,
+    rewrite: &mut R,
+) -> Result<Struct, R::Error> {
+    let mut rewritten = Struct::new();
+    for field in s.fields() {
+        let name = field.name().to_string();
+        let value = rewrite_value(field.value(), &child_path(path, &name), Some(&name), rewrite)?;
+        let mut field = Field::new();
+        field.set_name(name);
+        field.set_value(value);
+        rewritten.fields_mut().push(field);
+    }
+    Ok(rewritten)
+}
+
+pub(crate) fn rewrite_value<R: Rewrite>(
+    value: ValueView,
+    path: &str,
+    key: Option<&str>,
+    rewrite: &mut R,
+) -> Result<Value, R::Error> {
+    if let Some(replaced) = rewrite.before(path, key, value)? {
+        return Ok(replaced);
+    }
+    let value = match value.kind() {
+        KindOneof::StructValue(s) => {
+            proto!(Value { struct_value: rewrite_struct(s, path, rewrite)? })
+        }
+        KindOneof::ListValue(l) => {
+            let mut list_value = ListValue::new();
+            for (index, element) in l.values().iter().enumerate() {
+                let element_path = format!("{}[{}]", path, index);
+                list_value.values_mut().push(rewrite_value(element, &element_path, None, rewrite)?);
+            }
+            proto!(Value { list_value: list_value })
+        }
+        KindOneof::CallValue(call) if R::INTO_CALLS => {
+            proto!(Value { call_value: rewrite_call(call, path, rewrite)? })
+        }
+        _ => value.to_owned(),
+    };
+    rewrite.after(path, value)
+}
+
+/// Copies `tool_call` with its arguments rewritten, their paths starting
+/// from `path`.
+pub(crate) fn rewrite_call<R: Rewrite>(
+    tool_call: ToolCallView,
+    path: &str,
+    rewrite: &mut R,
+) -> Result<ToolCall, R::Error> {
+    // Every field but the arguments, which are built by the walk instead of
+    // being copied only to be replaced.
+    let mut rewritten = ToolCall::new();
+    rewritten.set_name(tool_call.name());
+    rewritten.set_spread_refs(tool_call.spread_refs().iter());
+    rewritten.set_comments(tool_call.comments().iter());
+    if let Some(confidence) = tool_call.confidence_opt().into_option() {
+        rewritten.set_confidence(confidence);
+    }
+    rewritten.set_dispatch_verb(tool_call.dispatch_verb());
+    if tool_call.has_arguments() {
+        rewritten.set_arguments(rewrite_struct(tool_call.arguments(), path, rewrite)?);
+    }
+    Ok(rewritten)
+}
+
+/// Runs `rewrite` over the arguments of every call in `tool_calls`, leaving
+/// them unchanged if it fails.
+pub(crate) fn rewrite_tool_calls<R: Rewrite>(
+    tool_calls: &mut ToolCalls,
+    rewrite: &mut R,
+) -> Result<(), R::Error> {
+    let rewritten = tool_calls
+        .tool_calls()
+        .iter()
+        .map(|tool_call| rewrite_call(tool_call, "", rewrite))
+        .collect::<Result<Vec<_>, _>>()?;
+    tool_calls.set_tool_calls(rewritten.into_iter());
+    Ok(())
+}
+
+// Runs a ValueTransformer bottom-up, on argument values only, not on the
+// arguments of nested calls.
+struct Transform<'a>(&'a ValueTransformer);
+
+impl Rewrite for Transform<'_> {
+    type Error = String;
+    const INTO_CALLS: bool = false;
+
+    fn after(&mut self, path: &str, value: Value) -> Result<Value, String> {
+        (self.0 .0)(path, value).map_err(|e| format!("Error parsing value at {}: {}", path, e))
+    }
+}
+
+/// Runs `transformer` on every argument value of `tool_calls`, bottom-up.
+pub(crate) fn transform_values(
+    tool_calls: &mut ToolCalls,
+    transformer: &ValueTransformer,
+) -> Result<(), String> {
+    rewrite_tool_calls(tool_calls, &mut Transform(transformer))
+}
+
+// Which numeric kind coerce_numbers rewrites values into.
+#[derive(Clone, Copy, PartialEq)]
+enum NumberKind {
+    Double,
+    ExactInt,
+}
+
+impl Rewrite for NumberKind {
+    type Error = std::convert::Infallible;
+
+    fn before(
+        &mut self,
+        _path: &str,
+        _key: Option<&str>,
+        value: ValueView,
+    ) -> Result<Option<Value>, Self::Error> {
+        Ok(match value.kind() {
+            KindOneof::IntValue(n) if *self == NumberKind::Double => {
+                Some(proto!(Value { number_value: n as f64 }))
+            }
+            // 2^63 itself is whole but one past i64::MAX, so the bound is
+            // exclusive.
+            KindOneof::NumberValue(n)
+                if *self == NumberKind::ExactInt
+                    && n.fract() == 0.0
+                    && n >= i64::MIN as f64
+                    && n < i64::MAX as f64 =>
+            {
+                Some(proto!(Value { int_value: n as i64 }))
+            }
+            _ => None,
+        })
+    }
+}
+
+fn coerce_numbers(tool_calls: &mut ToolCalls, mut kind: NumberKind) {
+    rewrite_tool_calls(tool_calls, &mut kind).unwrap_or_else(|never| match never {})
+}
+
+/// Rewrites every int_value in the arguments of `tool_calls`, at any depth
+/// and in nested calls, as a number_value, for stores that only hold
+/// doubles. Integers beyond 2^53 become the nearest double.
+pub fn coerce_numbers_to_double(tool_calls: &mut ToolCalls) {
+    coerce_numbers(tool_calls, NumberKind::Double)
+}
+
+/// The reverse of [`coerce_numbers_to_double`]: rewrites every number_value
+/// with no fractional part, such as `3.0`, as an int_value, leaving
+/// fractions, non-finite numbers and whole numbers outside the i64 range
+/// as they are. `-0.0` becomes 0.
+pub fn coerce_numbers_to_int_where_exact(tool_calls: &mut ToolCalls) {
+    coerce_numbers(tool_calls, NumberKind::ExactInt)
+}
+
+/// The newest ToolCalls wire format version. Each version adds to the one
+/// before it:
+///
+/// 1. null, number, string, bool, struct and list values.
+/// 2. int_value.
+/// 3. reference_value.
+/// 4. bytes_value, and spread_refs and comments on ToolCall.
+/// 5. call_value.
+/// 6. confidence on ToolCall.
+/// 7. dispatch_verb on ToolCall.
+pub const WIRE_FORMAT_VERSION: u32 = 7;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 (RFC 4648) with padding.
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// Maps each value kind newer than the target version onto an older one.
+struct Downgrade {
+    target_version: u32,
+}
+
+impl Downgrade {
+    // Downgrades a call, top-level or nested, and the fields it holds besides
+    // its arguments.
+    fn call(&mut self, tool_call: ToolCallView, path: &str) -> Result<ToolCall, String> {
+        let target_version = self.target_version;
+        if target_version < 4 && !tool_call.spread_refs().is_empty() {
+            return Err(format!(
+                "Call {} has spreads, which version {} cannot express",
+                tool_call.name(),
+                target_version
+            ));
+        }
+        if target_version < 7 && !tool_call.dispatch_verb().is_empty() {
+            return Err(format!(
+                "Call {} has dispatch verb {}, which version {} cannot express",
+                tool_call.name(),
+                tool_call.dispatch_verb(),
+                target_version
+            ));
+        }
+        let mut tool_call = rewrite_call(tool_call, path, self)?;
+        if target_version < 4 {
+            tool_call.set_comments(std::iter::empty::<String>());
+        }
+        if target_version < 6 {
+            tool_call.clear_confidence();
+        }
+        Ok(tool_call)
+    }
+}
+
+impl Rewrite for Downgrade {
+    type Error = String;
+
+    fn before(
+        &mut self,
+        path: &str,
+        _key: Option<&str>,
+        value: ValueView,
+    ) -> Result<Option<Value>, String> {
+        let target_version = self.target_version;
+        Ok(match value.kind() {
+            KindOneof::IntValue(n) if target_version < 2 => {
+                // Beyond 2^53 not every integer has an f64 of the same value.
+                if n.unsigned_abs() > 1 << 53 {
+                    return Err(format!("Integer {} at {} has no exact number_value", n, path));
+                }
+                Some(proto!(Value { number_value: n as f64 }))
+            }
+            KindOneof::ReferenceValue(name) if target_version < 3 => {
+                // The {"$ref": name} form of the JSON conversion.
+                let mut field = Field::new();
+                field.set_name("$ref");
+                field.set_value(proto!(Value { string_value: name }));
+                let mut reference = Struct::new();
+                reference.fields_mut().push(field);
+                Some(proto!(Value { struct_value: reference }))
+            }
+            KindOneof::BytesValue(bytes) if target_version < 4 => {
+                Some(proto!(Value { string_value: base64_encode(bytes) }))
+            }
+            KindOneof::CallValue(call) if target_version < 5 => {
+                return Err(format!(
+                    "Nested call {} at {} has no equivalent in version {}",
+                    call.name(),
+                    path,
+                    target_version
+                ))
+            }
+            KindOneof::CallValue(call) => {
+                Some(proto!(Value { call_value: self.call(call, path)? }))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Rewrites `tool_calls` so that a reader of wire format `target_version`
+/// understands it, mapping each value kind newer than that version onto an
+/// older one: int_value becomes number_value, reference_value a
+/// `{"$ref": name}` object, and bytes_value a base64 string_value. Comments
+/// are dropped below version 4 and confidence below version 6. Fails when the mapping would lose
+/// information: an integer beyond 2^53 for version 1, spread_refs for any
+/// version before 4, since the arguments cannot express them, a nested call
+/// for any version before 5, or a dispatch verb, which changes how the call
+/// is run, for any version before 7. Also fails on a version that is 0 or newer
+/// than [`WIRE_FORMAT_VERSION`].
+pub fn downgrade(tool_calls: &ToolCalls, target_version: u32) -> Result<ToolCalls, String> {
+    if target_version == 0 || target_version > WIRE_FORMAT_VERSION {
+        return Err(format!("Unknown wire format version {}", target_version));
+    }
+    let mut downgraded = ToolCalls::new();
+    for tool_call in tool_calls.tool_calls() {
+        let tool_call = Downgrade { target_version }
+            .call(tool_call, "")
+            .map_err(|e| format!("Error downgrading call {}: {}", tool_call.name(), e))?;
+        downgraded.tool_calls_mut().push(tool_call);
+    }
+    Ok(downgraded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::{prelude::*, proto};
+    use tool_call_rust_proto::{ListValue, Struct, ToolCall};
+
+    fn number(n: f64) -> Value {
+        proto!(Value { number_value: n })
+    }
+
+    fn object(fields: &[(&str, Value)]) -> Value {
+        let mut s = Struct::new();
+        for (name, value) in fields {
+            let mut field = Field::new();
+            field.set_name(*name);
+            field.set_value(value.clone());
+            s.fields_mut().push(field);
+        }
+        proto!(Value { struct_value: s })
+    }
+
+    fn list(values: &[Value]) -> Value {
+        let mut l = ListValue::new();
+        for value in values {
+            l.values_mut().push(value.clone());
+        }
+        proto!(Value { list_value: l })
+    }
+
+    #[test]
+    fn struct_equality_ignores_field_order() {
+        let a =
+            object(&[("x", number(1.0)), ("y", object(&[("p", number(2.0)), ("q", number(3.0))]))]);
+        let b =
+            object(&[("y", object(&[("q", number(3.0)), ("p", number(2.0))])), ("x", number(1.0))]);
+        assert!(value_eq(&a, &b));
+
+        let c = object(&[("x", number(1.0)), ("y", number(4.0))]);
+        assert!(!value_eq(&a, &c));
+    }
+
+    #[test]
+    fn list_equality_is_order_sensitive() {
+        let a = list(&[number(1.0), number(2.0)]);
+        let b = list(&[number(2.0), number(1.0)]);
+        assert!(value_eq(&a, &a.clone()));
+        assert!(!value_eq(&a, &b));
+    }
+
+    #[test]
+    fn normalize_sorts_nested_struct_fields() {
+        let mut value = object(&[
+            ("b", list(&[object(&[("z", number(1.0)), ("a", number(2.0))])])),
+            ("a", number(3.0)),
+        ]);
+        let expected = object(&[
+            ("a", number(3.0)),
+            ("b", list(&[object(&[("a", number(2.0)), ("z", number(1.0))])])),
+        ]);
+        normalize(&mut value);
+        assert_eq!(value.serialize().unwrap(), expected.serialize().unwrap());
+    }
+
+    #[test]
+    fn reports_only_changed_calls() {
+        let mut original = ToolCalls::new();
+        for name in ["a", "b", "c"] {
+            let mut tool_call = ToolCall::new();
+            tool_call.set_name(name);
+            let mut arguments = Struct::new();
+            let mut field = Field::new();
+            field.set_name("n");
+            field.set_value(proto!(Value { string_value: "1" }));
+            arguments.fields_mut().push(field);
+            tool_call.set_arguments(arguments);
+            original.tool_calls_mut().push(tool_call);
+        }
+
+        // Coerce the string argument of the second call to a number.
+        let mut modified = original.clone();
+        let mut arguments = modified.tool_calls().get(1).unwrap().arguments().to_owned();
+        let mut field = arguments.fields().get(0).unwrap().to_owned();
+        field.set_value(number(1.0));
+        arguments.set_fields(std::iter::once(field));
+        modified.tool_calls_mut().get_mut(1).unwrap().set_arguments(arguments);
+
+        assert_eq!(changed_indices(&original, &original.clone()), Vec::<usize>::new());
+        assert_eq!(changed_indices(&original, &modified), vec![1]);
+        modified.tool_calls_mut().push(ToolCall::new());
+        assert_eq!(changed_indices(&original, &modified), vec![1, 3]);
+
+        // Every other field counts too.
+        let edits: [fn(&mut ToolCalls); 4] = [
+            |t| t.tool_calls_mut().get_mut(0).unwrap().set_spread_refs(["base"].into_iter()),
+            |t| t.tool_calls_mut().get_mut(0).unwrap().set_comments(["// note"].into_iter()),
+            |t| t.tool_calls_mut().get_mut(0).unwrap().set_confidence(0.5),
+            |t| t.tool_calls_mut().get_mut(0).unwrap().set_dispatch_verb("INVOKE"),
+        ];
+        for edit in edits {
+            let mut modified = original.clone();
+            edit(&mut modified);
+            assert_eq!(changed_indices(&original, &modified), vec![0]);
+        }
+    }
+
+    #[test]
+    fn coerces_numbers_in_both_directions() {
+        let int = |n: i64| proto!(Value { int_value: n });
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name("store");
+        let arguments = object(&[
+            ("count", int(3)),
+            ("ratio", number(2.5)),
+            ("nested", object(&[("whole", number(-4.0)), ("list", list(&[int(1), number(0.5)]))])),
+        ]);
+        tool_call.set_arguments(arguments.struct_value().to_owned());
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(tool_call);
+        let arguments = |tool_calls: &ToolCalls| {
+            proto!(Value {
+                struct_value: tool_calls.tool_calls().get(0).unwrap().arguments().to_owned()
+            })
+        };
+
+        coerce_numbers_to_double(&mut tool_calls);
+        let doubles = object(&[
+            ("count", number(3.0)),
+            ("ratio", number(2.5)),
+            (
+                "nested",
+                object(&[("whole", number(-4.0)), ("list", list(&[number(1.0), number(0.5)]))]),
+            ),
+        ]);
+        assert!(value_eq(&arguments(&tool_calls), &doubles));
+
+        coerce_numbers_to_int_where_exact(&mut tool_calls);
+        let ints = object(&[
+            ("count", int(3)),
+            ("ratio", number(2.5)),
+            ("nested", object(&[("whole", int(-4)), ("list", list(&[int(1), number(0.5)]))])),
+        ]);
+        assert!(value_eq(&arguments(&tool_calls), &ints));
+    }
+
+    #[test]
+    fn rewrites_keep_every_field_of_a_call() {
+        let call = |n: Value, nested: Option<ToolCall>| {
+            let mut tool_call = ToolCall::new();
+            tool_call.set_name("f");
+            tool_call.set_spread_refs(["base"].into_iter());
+            tool_call.set_comments(["// go"].into_iter());
+            tool_call.set_confidence(0.5);
+            tool_call.set_dispatch_verb("INVOKE");
+            let mut fields = vec![("n", n)];
+            if let Some(nested) = nested {
+                fields.push(("inner", proto!(Value { call_value: nested })));
+            }
+            tool_call.set_arguments(object(&fields).struct_value().to_owned());
+            tool_call
+        };
+        let int =
+            call(proto!(Value { int_value: 2 }), Some(call(proto!(Value { int_value: 1 }), None)));
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(int);
+
+        coerce_numbers_to_double(&mut tool_calls);
+        let expected = call(number(2.0), Some(call(number(1.0), None)));
+        assert!(value_eq(
+            &proto!(Value { call_value: tool_calls.tool_calls().get(0).unwrap().to_owned() }),
+            &proto!(Value { call_value: expected })
+        ));
+    }
+
+    #[test]
+    fn downgrade_maps_newer_kinds_onto_older_ones() {
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name("upload");
+        let arguments = object(&[
+            ("count", proto!(Value { int_value: 3 })),
+            ("data", list(&[proto!(Value { bytes_value: b"hi!?".to_vec() })])),
+        ]);
+        tool_call.set_arguments(arguments.struct_value().to_owned());
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(tool_call);
+
+        let downgraded = downgrade(&tool_calls, 1).unwrap();
+        let expected = object(&[
+            ("count", number(3.0)),
+            ("data", list(&[proto!(Value { string_value: "aGkhPw==" })])),
+        ]);
+        let arguments = downgraded.tool_calls().get(0).unwrap().arguments().to_owned();
+        assert!(value_eq(&proto!(Value { struct_value: arguments }), &expected));
+        let current = downgrade(&tool_calls, WIRE_FORMAT_VERSION).unwrap();
+        assert_eq!(current.serialize().unwrap(), tool_calls.serialize().unwrap());
+
+        let mut big = ToolCalls::new();
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name("f");
+        tool_call.set_arguments(
+            object(&[("n", proto!(Value { int_value: i64::MAX }))]).struct_value().to_owned(),
+        );
+        big.tool_calls_mut().push(tool_call);
+        assert!(downgrade(&big, 1).is_err());
+        assert!(downgrade(&big, 2).is_ok());
+        assert!(downgrade(&big, 0).is_err());
+    }
+
+    #[test]
+    fn downgrade_drops_confidence_below_version_6() {
+        let mut inner = ToolCall::new();
+        inner.set_name("g");
+        inner.set_confidence(0.5);
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name("f");
+        tool_call.set_confidence(0.87);
+        tool_call.set_arguments(
+            object(&[("n", proto!(Value { call_value: inner }))]).struct_value().to_owned(),
+        );
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(tool_call);
+
+        let kept = downgrade(&tool_calls, WIRE_FORMAT_VERSION).unwrap();
+        assert_eq!(kept.serialize().unwrap(), tool_calls.serialize().unwrap());
+        let downgraded = downgrade(&tool_calls, 5).unwrap();
+        let tool_call = downgraded.tool_calls().get(0).unwrap();
+        assert!(!tool_call.has_confidence());
+        let inner = tool_call.arguments().fields().get(0).unwrap().value().call_value();
+        assert_eq!(inner.name(), "g");
+        assert!(!inner.has_confidence());
+    }
+
+    #[test]
+    fn downgrade_rejects_dispatch_verbs_below_version_7() {
+        let mut inner = ToolCall::new();
+        inner.set_name("g");
+        inner.set_dispatch_verb("INVOKE");
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name("f");
+        tool_call.set_arguments(
+            object(&[("n", proto!(Value { call_value: inner }))]).struct_value().to_owned(),
+        );
+        let mut tool_calls = ToolCalls::new();
+        tool_calls.tool_calls_mut().push(tool_call);
+
+        assert!(downgrade(&tool_calls, 7).is_ok());
+        let err = downgrade(&tool_calls, 6).unwrap_err();
+        assert_eq!(
+            err,
+            "Error downgrading call f: Call g has dispatch verb INVOKE, which version 6 cannot \
+             express"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2025 The Google AI Edge Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion between ToolCalls and the google.protobuf.Struct well-known
+//! type used by gRPC services.
+
+use protobuf::proto;
+use protobuf_well_known_types as wkt;
+use tool_call_rust_proto::value::KindOneof;
+use tool_call_rust_proto::{
+    Field, ListValue, NullValue, Struct, StructView, ToolCall, ToolCalls, Value, ValueView,
+};
+
+fn struct_to_google(s: StructView) -> wkt::Struct {
+    let mut google = wkt::Struct::new();
+    for field in s.fields() {
+        google.fields_mut().insert(field.name(), value_to_google(field.value()));
+    }
+    google
+}
+
+// Integers become numbers, and as in the JSON form bytes become a base64
+// string, a reference {"$ref": name} and a nested call
+// {"$call": {"name": ..., "arguments": ...}}.
+fn value_to_google(value: ValueView) -> wkt::Value {
+    match value.kind() {
+        KindOneof::NumberValue(n) => proto!(wkt::Value { number_value: n }),
+        KindOneof::IntValue(n) => proto!(wkt::Value { number_value: n as f64 }),
+        KindOneof::StringValue(s) => proto!(wkt::Value { string_value: s }),
+        KindOneof::BoolValue(b) => proto!(wkt::Value { bool_value: b }),
+        KindOneof::ReferenceValue(name) => {
+            let mut reference = wkt::Struct::new();
+            reference.fields_mut().insert("$ref", proto!(wkt::Value { string_value: name }));
+            proto!(wkt::Value { struct_value: reference })
+        }
+        KindOneof::BytesValue(bytes) => {
+            proto!(wkt::Value { string_value: crate::value_utils::base64_encode(bytes) })
+        }
+        KindOneof::CallValue(call) => {
+            let mut tool_call = wkt::Struct::new();
+            tool_call.fields_mut().insert("name", proto!(wkt::Value { string_value: call.name() }));
+            tool_call.fields_mut().insert(
+                "arguments",
+                proto!(wkt::Value { struct_value: struct_to_google(call.arguments()) }),
+            );
+            let mut nested = wkt::Struct::new();
+            nested.fields_mut().insert("$call", proto!(wkt::Value { struct_value: tool_call }));
+            proto!(wkt::Value { struct_value: nested })
+        }
+        KindOneof::StructValue(s) => proto!(wkt::Value { struct_value: struct_to_google(s) }),
+        KindOneof::ListValue(l) => {
+            let mut list_value = wkt::ListValue::new();
+            for element in l.values() {
+                list_value.values_mut().push(value_to_google(element));
+            }
+            proto!(wkt::Value { list_value: list_value })
+        }
+        _ => proto!(wkt::Value { null_value: wkt::NullValue::default() }),
+    }
+}
+
+fn struct_from_google(google: wkt::StructView) -> Struct {
+    // Map order is unspecified, so fields are sorted by key.
+    let mut entries: Vec<_> = google.fields().iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let mut s = Struct::new();
+    for (name, value) in entries {
+        let mut field = Field::new();
+        field.set_name(name);
+        field.set_value(value_from_google(value));
+        s.fields_mut().push(field);
+    }
+    s
+}
+
+fn value_from_google(google: wkt::ValueView) -> Value {
+    use wkt::value::KindOneof as GoogleKind;
+    match google.kind() {
+        GoogleKind::NumberValue(n) => proto!(Value { number_value: n }),
+        GoogleKind::StringValue(s) => proto!(Value { string_value: s }),
+        GoogleKind::BoolValue(b) => proto!(Value { bool_value: b }),
+        GoogleKind::StructValue(s) => proto!(Value { struct_value: struct_from_google(s) }),
+        GoogleKind::ListValue(l) => {
+            let mut list_value = ListValue::new();
+            for element in l.values() {
+                list_value.values_mut().push(value_from_google(element));
+            }
+            proto!(Value { list_value: list_value })
+        }
+        _ => proto!(Value { null_value: NullValue::default() }),
+    }
+}
+
+/// Converts `tool_calls` into a google.protobuf.Struct with one field per
+/// call, from the function name to the arguments object. A repeated name
+/// keeps its last call.
+pub fn to_google_struct(tool_calls: &ToolCalls) -> wkt::Struct {
+    let mut google = wkt::Struct::new();
+    for tool_call in tool_calls.tool_calls() {
+        let arguments = struct_to_google(tool_call.arguments());
+        google
+            .fields_mut()
+            .insert(tool_call.name(), proto!(wkt::Value { struct_value: arguments }));
+    }
+    google
+}
+
+/// Converts a Struct built by [`to_google_struct`] back into calls sorted by
+/// name, with their arguments sorted by key. Integers come back as
+/// number_value, bytes as base64 strings, references as `$ref` objects and
+/// nested calls as `$call` objects. A field that does not hold
+/// an object is an error.
+pub fn from_google_struct(google: &wkt::Struct) -> Result<ToolCalls, String> {
+    let mut entries: Vec<_> = google.fields().iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let mut tool_calls = ToolCalls::new();
+    for (name, value) in entries {
+        let wkt::value::KindOneof::StructValue(arguments) = value.kind() else {
+            return Err(format!("Arguments of {} are not an object", name));
+        };
+        let mut tool_call = ToolCall::new();
+        tool_call.set_name(name);
+        tool_call.set_arguments(struct_from_google(arguments));
+        tool_calls.tool_calls_mut().push(tool_call);
+    }
+    Ok(tool_calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fc_expression, value_eq};
+
+    #[test]
+    fn round_trips_through_google_struct() {
+        let result = parse_fc_expression(
+            "call:search{query:<escape>cats<escape>,limit:5,filters:{safe:true,tags:[<escape>a<escape>,null]}}, call:open{}",
+        );
+        assert!(result.is_ok, "{}", result.error);
+        let tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+
+        let google = to_google_struct(&tool_calls);
+        assert_eq!(google.fields().len(), 2);
+        let round_tripped = from_google_struct(&google).unwrap();
+        assert_eq!(round_tripped.tool_calls().len(), 2);
+        for tool_call in tool_calls.tool_calls() {
+            let other = round_tripped
+                .tool_calls()
+                .iter()
+                .find(|other| other.name() == tool_call.name())
+                .unwrap();
+            let (a, b) = (tool_call.arguments().to_owned(), other.arguments().to_owned());
+            assert!(value_eq(
+                &proto!(Value { struct_value: a }),
+                &proto!(Value { struct_value: b })
+            ));
+        }
+
+        let mut bad = wkt::Struct::new();
+        bad.fields_mut().insert("f", proto!(wkt::Value { number_value: 1.0 }));
+        assert_eq!(from_google_struct(&bad).unwrap_err(), "Arguments of f are not an object");
+    }
+
+    #[test]
+    fn converts_nested_calls_and_bytes_instead_of_dropping_them() {
+        let options = crate::ParseOptions { nested_calls: true, ..Default::default() };
+        let result = crate::parse_fc_expression_with_options("call:f{n:call:g{x:1}}", &options);
+        assert!(result.is_ok, "{}", result.error);
+        let mut tool_calls = ToolCalls::parse(&result.serialized_tool_calls).unwrap();
+        let mut blob = Field::new();
+        blob.set_name("blob");
+        blob.set_value(proto!(Value { bytes_value: b"hi".to_vec() }));
+        tool_calls.tool_calls_mut().get_mut(0).unwrap().arguments_mut().fields_mut().push(blob);
+
+        let google = to_google_struct(&tool_calls);
+        let arguments = google.fields().get("f").unwrap().struct_value();
+        assert_eq!(arguments.fields().get("blob").unwrap().string_value(), "aGk=");
+        let nested = arguments.fields().get("n").unwrap().struct_value();
+        let call = nested.fields().get("$call").unwrap().struct_value();
+        assert_eq!(call.fields().get("name").unwrap().string_value(), "g");
+        let x = call.fields().get("arguments").unwrap().struct_value().fields().get("x").unwrap();
+        assert_eq!(x.number_value(), 1.0);
+    }
+}